@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+
+use async_trait::async_trait;
+use ethers::types::{
+    Bloom,
+    Transaction as RlpTransaction,
+    H160,
+    H256,
+    H64,
+    U256,
+};
+use ethers::utils::rlp::Rlp;
+use ethers::utils::{
+    hex,
+    keccak256,
+};
+use serde_json::{
+    json,
+    Value,
+};
+use snap::read::FrameDecoder;
+
+use crate::archive::source::BlockSource;
+use crate::rpc::format::decimal_to_hex;
+use crate::rpc::types::{
+    BlockResult,
+    Transaction,
+};
+
+// e2store entry types used by era1 files.
+// See: https://github.com/ethereum/go-ethereum/blob/master/internal/era/era.go
+const COMPRESSED_HEADER: u16 = 0x03;
+const COMPRESSED_BODY: u16 = 0x04;
+const TOTAL_DIFFICULTY: u16 = 0x06;
+
+// Size of the type, length and reserved fields in front of every e2store entry
+const ENTRY_HEADER_SIZE: usize = 8;
+
+#[derive(Default)]
+struct Era1Block {
+    header: Vec<u8>,
+    body: Range<usize>,
+    total_difficulty: Option<U256>,
+}
+
+// Pre-merge block history in the era1 format. Every file holds up to 8192 blocks
+// as snappy compressed RLP headers and bodies, which we turn back into `BlockResult`s.
+pub struct Era1File {
+    path: String,
+    data: Vec<u8>,
+    blocks: BTreeMap<u64, Era1Block>,
+}
+
+impl Era1File {
+    pub fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let data = fs::read(path)?;
+        let mut blocks = BTreeMap::new();
+
+        // Entries of a block always come as header, body, receipts and total difficulty
+        let mut current_block = 0;
+        let mut offset = 0;
+        while offset + ENTRY_HEADER_SIZE <= data.len() {
+            let entry_type = u16::from_le_bytes([data[offset], data[offset + 1]]);
+            let length = u32::from_le_bytes([
+                data[offset + 2],
+                data[offset + 3],
+                data[offset + 4],
+                data[offset + 5],
+            ]) as usize;
+            let value = offset + ENTRY_HEADER_SIZE..offset + ENTRY_HEADER_SIZE + length;
+            if value.end > data.len() {
+                return Err(format!("Era1 file {} is truncated", path).into());
+            }
+
+            match entry_type {
+                COMPRESSED_HEADER => {
+                    let header = decompress(&data[value])?;
+                    current_block = Rlp::new(&header).val_at(8)?;
+                    blocks.insert(
+                        current_block,
+                        Era1Block {
+                            header,
+                            ..Default::default()
+                        },
+                    );
+                }
+                COMPRESSED_BODY => {
+                    if let Some(block) = blocks.get_mut(&current_block) {
+                        block.body = value;
+                    }
+                }
+                TOTAL_DIFFICULTY => {
+                    if let Some(block) = blocks.get_mut(&current_block) {
+                        // Total difficulty is stored as a little endian uint256
+                        block.total_difficulty = Some(U256::from_little_endian(&data[value]));
+                    }
+                }
+                _ => (),
+            }
+
+            offset += ENTRY_HEADER_SIZE + length;
+        }
+
+        match (blocks.keys().next(), blocks.keys().last()) {
+            (Some(first), Some(last)) => {
+                println!("Opened era1 file with blocks {} to {}", first, last)
+            }
+            _ => return Err(format!("Era1 file {} contains no blocks", path).into()),
+        }
+
+        Ok(Self {
+            path: path.to_string(),
+            data,
+            blocks,
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl BlockSource for Era1File {
+    // Era1 files don't store the chain id, but they are named after the network
+    async fn get_chain_id(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let filename = Path::new(&self.path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        let chain_id = match filename.split('-').next() {
+            Some("mainnet") => Some("0x1".to_string()),
+            Some("goerli") => Some("0x5".to_string()),
+            Some("sepolia") => Some("0xaa36a7".to_string()),
+            _ => None,
+        };
+
+        Ok(chain_id)
+    }
+
+    async fn get_block(
+        &mut self,
+        block_number: u64,
+    ) -> Result<BlockResult, Box<dyn std::error::Error>> {
+        let block = match self.blocks.get(&block_number) {
+            Some(block) => block,
+            None => {
+                return Err(format!(
+                    "Block {} is not in the era1 file {}",
+                    block_number, self.path
+                )
+                .into())
+            }
+        };
+
+        let body = decompress(&self.data[block.body.clone()])?;
+        decode_block(&block.header, &body, block.total_difficulty)
+    }
+}
+
+fn decompress(data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let mut decompressed = Vec::new();
+    FrameDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+// Build a `BlockResult` out of the RLP encoded header and body of a block.
+fn decode_block(
+    header: &[u8],
+    body: &[u8],
+    total_difficulty: Option<U256>,
+) -> Result<BlockResult, Box<dyn std::error::Error>> {
+    let header_rlp = Rlp::new(header);
+    let body_rlp = Rlp::new(body);
+
+    let hash = H256(keccak256(header));
+    let number: u64 = header_rlp.val_at(8)?;
+
+    let mut transactions = Vec::new();
+    for (index, tx_rlp) in body_rlp.at(0)?.iter().enumerate() {
+        let mut tx: RlpTransaction = tx_rlp.as_val()?;
+        tx.hash = tx.hash();
+        tx.recover_from_mut()?;
+        transactions.push(to_replay_transaction(tx, hash, number, index as u64));
+    }
+
+    let uncles: Vec<String> = body_rlp
+        .at(1)?
+        .iter()
+        .map(|uncle| format!("{:?}", H256(keccak256(uncle.as_raw()))))
+        .collect();
+
+    // London headers have a 16th field with the base fee
+    let base_fee: Option<U256> = match header_rlp.item_count()? > 15 {
+        true => Some(header_rlp.val_at(15)?),
+        false => None,
+    };
+
+    let block = json!({
+        "baseFeePerGas": base_fee.map(|fee| format!("{:#x}", fee)),
+        "difficulty": format!("{:#x}", header_rlp.val_at::<U256>(7)?),
+        "extraData": format!("0x{}", hex::encode(header_rlp.val_at::<Vec<u8>>(12)?)),
+        "gasLimit": format!("{:#x}", header_rlp.val_at::<U256>(9)?),
+        "gasUsed": format!("{:#x}", header_rlp.val_at::<U256>(10)?),
+        "hash": format!("{:?}", hash),
+        "logsBloom": format!("{:?}", header_rlp.val_at::<Bloom>(6)?),
+        "miner": format!("{:?}", header_rlp.val_at::<H160>(2)?),
+        "mixHash": format!("{:?}", header_rlp.val_at::<H256>(13)?),
+        "nonce": format!("{:?}", header_rlp.val_at::<H64>(14)?),
+        "number": decimal_to_hex(number),
+        "parentHash": format!("{:?}", header_rlp.val_at::<H256>(0)?),
+        "receiptsRoot": format!("{:?}", header_rlp.val_at::<H256>(5)?),
+        "sha3Uncles": format!("{:?}", header_rlp.val_at::<H256>(1)?),
+        "size": decimal_to_hex((header.len() + body.len()) as u64),
+        "stateRoot": format!("{:?}", header_rlp.val_at::<H256>(3)?),
+        "timestamp": decimal_to_hex(header_rlp.val_at::<u64>(11)?),
+        "totalDifficulty": total_difficulty.map(|td| format!("{:#x}", td)),
+        "transactions": transactions,
+        "transactionsRoot": format!("{:?}", header_rlp.val_at::<H256>(4)?),
+        "uncles": uncles,
+    });
+
+    Ok(serde_json::from_value(block)?)
+}
+
+// Convert a decoded tx into the JSON-RPC shaped tx we replay.
+fn to_replay_transaction(
+    tx: RlpTransaction,
+    block_hash: H256,
+    number: u64,
+    index: u64,
+) -> Transaction {
+    let access_list = tx.access_list.map(|list| {
+        list.0
+            .iter()
+            .map(|item| serde_json::to_value(item).unwrap_or(Value::Null))
+            .collect()
+    });
+
+    Transaction {
+        blockHash: format!("{:?}", block_hash),
+        blockNumber: decimal_to_hex(number),
+        hash: format!("{:?}", tx.hash),
+        accessList: access_list,
+        chainId: tx.chain_id.map(|id| format!("{:#x}", id)),
+        from: format!("{:?}", tx.from),
+        gas: format!("{:#x}", tx.gas),
+        // Typed txs don't have a gas price, same as nodes we report the fee cap
+        gasPrice: format!(
+            "{:#x}",
+            tx.gas_price.or(tx.max_fee_per_gas).unwrap_or_default()
+        ),
+        input: tx.input.to_string(),
+        maxFeePerGas: tx.max_fee_per_gas.map(|fee| format!("{:#x}", fee)),
+        maxPriorityFeePerGas: tx.max_priority_fee_per_gas.map(|fee| format!("{:#x}", fee)),
+        nonce: format!("{:#x}", tx.nonce),
+        r: format!("{:#x}", tx.r),
+        s: format!("{:#x}", tx.s),
+        to: tx.to.map(|to| format!("{:?}", to)),
+        transactionIndex: decimal_to_hex(index),
+        txType: format!("{:#x}", tx.transaction_type.unwrap_or_default()),
+        v: format!("{:#x}", tx.v),
+        value: format!("{:#x}", tx.value),
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs::{
+    self,
+    File,
+};
+use std::io::{
+    self,
+    BufReader,
+    BufWriter,
+    Write,
+};
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::sync::Arc;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::Value;
+
+use crate::archive::types::*;
+use crate::rpc::format::{
+    decimal_to_hex,
+    hex_to_decimal,
+};
+use crate::tracker::time::get_latest_unix_timestamp;
+use crate::RpcConnection;
+
+// Download blocks from `origin_block` to `terminal_block` (inclusive) and write them
+// to a gzip compressed JSONL file. The first line is an `ArchiveHeader`, and every
+// line after it is an `ArchiveEntry` with the full block and optionally its receipts.
+//
+// The header has to say which blocks the archive really contains, so blocks are written to
+// a temporary file first, and the archive gets written once we know the last one we exported.
+pub async fn export_blocks(
+    source_rpc: RpcConnection,
+    origin_block: u64,
+    terminal_block: Option<u64>,
+    with_receipts: bool,
+    path: String,
+    filename: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let interrupted_clone = interrupted.clone();
+
+    ctrlc::set_handler(move || {
+        interrupted_clone.store(true, Ordering::SeqCst);
+    })?;
+
+    let terminal_block = match terminal_block {
+        Some(terminal_block) => terminal_block,
+        None => {
+            let a = hex_to_decimal(&source_rpc.block_number().await?)?;
+            println!(
+                "No terminal block set, setting terminal block to current head: {}",
+                a
+            );
+            a
+        }
+    };
+
+    if origin_block > terminal_block {
+        return Err("Origin block cannot be higher than the terminal block".into());
+    }
+
+    let mut header = ArchiveHeader {
+        chain_id: source_rpc.chain_id().await?,
+        origin_block,
+        terminal_block,
+        fetched_at: get_latest_unix_timestamp(),
+        receipts: with_receipts,
+    };
+
+    let filename = match filename.as_str() {
+        "" => {
+            format!(
+                "blocks-{}-{}-timestamp-{}.jsonl.gz",
+                origin_block, terminal_block, header.fetched_at
+            )
+        }
+        _ => filename,
+    };
+    let path = format!("{}/{}", path, filename);
+    println!("Writing blocks to file: {}", path);
+
+    let blocks_path = format!("{}.tmp", path);
+    let mut blocks = GzEncoder::new(
+        BufWriter::new(File::create(&blocks_path)?),
+        Compression::default(),
+    );
+    let mut last_exported = None;
+
+    let result: Result<(), Box<dyn std::error::Error>> = async {
+        let mut current_block = origin_block;
+        while current_block <= terminal_block {
+            if interrupted.load(Ordering::SeqCst) {
+                println!("!!! \x1b[93mInterrupted, stopping the export\x1b[0m !!!");
+                break;
+            }
+
+            let hex_block = decimal_to_hex(current_block);
+            let block: Value =
+                serde_json::from_str(&source_rpc.get_block_by_number(hex_block.clone()).await?)?;
+
+            let receipts = match with_receipts {
+                true => Some(get_receipts(&source_rpc, hex_block, &block).await?),
+                false => None,
+            };
+
+            let entry = ArchiveEntry { block, receipts };
+            writeln!(blocks, "{}", serde_json::to_string(&entry)?)?;
+            last_exported = Some(current_block);
+            println!("Exported block {}", current_block);
+
+            current_block += 1;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    blocks.finish()?.flush()?;
+    match last_exported {
+        Some(last_exported) => {
+            if last_exported < terminal_block {
+                println!(
+                    "!!! \x1b[93mWARNING:\x1b[0m The archive ends at block {} instead of {} !!!",
+                    last_exported, terminal_block
+                );
+            }
+            header.terminal_block = last_exported;
+            write_archive(&path, &header, &blocks_path)?;
+            println!("Done exporting blocks");
+        }
+        None => println!("!!! \x1b[93mWARNING:\x1b[0m No blocks were exported !!!"),
+    }
+    fs::remove_file(&blocks_path)?;
+
+    result
+}
+
+// Write the archive to `path`, with `header` followed by the blocks in `blocks_path`.
+fn write_archive(
+    path: &str,
+    header: &ArchiveHeader,
+    blocks_path: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut archive = GzEncoder::new(BufWriter::new(File::create(path)?), Compression::default());
+    writeln!(archive, "{}", serde_json::to_string(header)?)?;
+
+    let mut blocks = GzDecoder::new(BufReader::new(File::open(blocks_path)?));
+    io::copy(&mut blocks, &mut archive)?;
+    archive.finish()?.flush()?;
+
+    Ok(())
+}
+
+// Not every node supports `eth_getBlockReceipts`, fall back to getting them one by one.
+pub async fn get_receipts(
+    source_rpc: &RpcConnection,
+    hex_block: String,
+    block: &Value,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    if let Ok(receipts) = source_rpc.get_block_receipts(hex_block).await {
+        return Ok(serde_json::from_str(&receipts)?);
+    }
+
+    let mut receipts = Vec::new();
+    if let Some(transactions) = block["transactions"].as_array() {
+        for tx in transactions {
+            let hash = tx["hash"].as_str().unwrap_or_default().to_string();
+            let receipt = source_rpc.get_transaction_receipt(hash).await?;
+            receipts.push(serde_json::from_str::<Value>(&receipt)?);
+        }
+    }
+
+    Ok(Value::Array(receipts))
+}
@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io::{
+    BufRead,
+    BufReader,
+    Lines,
+};
+
+use async_trait::async_trait;
+use flate2::read::GzDecoder;
+
+use crate::archive::source::BlockSource;
+use crate::archive::types::*;
+use crate::rpc::format::hex_to_decimal;
+use crate::rpc::types::BlockResult;
+
+type ArchiveLines = Lines<Box<dyn BufRead>>;
+
+// Block archive written by the export mode. Replay reads blocks in order, so
+// we stream through the file and only reopen it if a block before the cursor is requested.
+pub struct ArchiveFile {
+    path: String,
+    header: ArchiveHeader,
+    lines: ArchiveLines,
+    // Number of the next block the cursor will read
+    next_block: u64,
+}
+
+impl ArchiveFile {
+    pub fn open(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (header, lines) = Self::read_header(path)?;
+        println!(
+            "Opened block archive with blocks {} to {} of chain {}",
+            header.origin_block, header.terminal_block, header.chain_id
+        );
+
+        Ok(Self {
+            path: path.to_string(),
+            next_block: header.origin_block,
+            header,
+            lines,
+        })
+    }
+
+    fn read_header(
+        path: &str,
+    ) -> Result<(ArchiveHeader, ArchiveLines), Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        let reader: Box<dyn BufRead> = match path.ends_with(".gz") {
+            true => Box::new(BufReader::new(GzDecoder::new(file))),
+            false => Box::new(BufReader::new(file)),
+        };
+
+        let mut lines = reader.lines();
+        let header = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Err(format!("Block archive {} is empty", path).into()),
+        };
+
+        Ok((header, lines))
+    }
+}
+
+#[async_trait(?Send)]
+impl BlockSource for ArchiveFile {
+    async fn get_chain_id(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        Ok(Some(self.header.chain_id.clone()))
+    }
+
+    async fn get_block(
+        &mut self,
+        block_number: u64,
+    ) -> Result<BlockResult, Box<dyn std::error::Error>> {
+        if block_number < self.next_block {
+            let (_, lines) = Self::read_header(&self.path)?;
+            self.lines = lines;
+            self.next_block = self.header.origin_block;
+        }
+
+        for line in self.lines.by_ref() {
+            let entry: ArchiveEntry = serde_json::from_str(&line?)?;
+            let block: BlockResult = serde_json::from_value(entry.block)?;
+            self.next_block = hex_to_decimal(&block.number)? + 1;
+
+            if self.next_block - 1 == block_number {
+                return Ok(block);
+            }
+        }
+
+        Err(format!("Block {} is not in the archive {}", block_number, self.path).into())
+    }
+}
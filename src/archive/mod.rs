@@ -0,0 +1,5 @@
+pub mod era1;
+pub mod export;
+pub mod file;
+pub mod source;
+pub mod types;
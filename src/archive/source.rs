@@ -0,0 +1,47 @@
+use async_trait::async_trait;
+
+use crate::archive::era1::Era1File;
+use crate::archive::file::ArchiveFile;
+use crate::rpc::format::decimal_to_hex;
+use crate::rpc::types::BlockResult;
+use crate::RpcConnection;
+
+// Anything we can get historical blocks from.
+#[async_trait(?Send)]
+pub trait BlockSource {
+    // Chain id of the blocks, or `None` if the source doesn't know it.
+    async fn get_chain_id(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>>;
+
+    // Gets a block with full transactions by its number.
+    async fn get_block(
+        &mut self,
+        block_number: u64,
+    ) -> Result<BlockResult, Box<dyn std::error::Error>>;
+}
+
+#[async_trait(?Send)]
+impl BlockSource for RpcConnection {
+    async fn get_chain_id(&mut self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        Ok(Some(self.chain_id().await?))
+    }
+
+    async fn get_block(
+        &mut self,
+        block_number: u64,
+    ) -> Result<BlockResult, Box<dyn std::error::Error>> {
+        let block = self
+            .get_block_by_number(decimal_to_hex(block_number))
+            .await?;
+        Ok(serde_json::from_str(&block)?)
+    }
+}
+
+// Open a block file as a source. `.era1` files are read as era1, everything
+// else as an archive created by the export mode.
+pub fn open_block_file(path: &str) -> Result<Box<dyn BlockSource>, Box<dyn std::error::Error>> {
+    if path.ends_with(".era1") {
+        Ok(Box::new(Era1File::open(path)?))
+    } else {
+        Ok(Box::new(ArchiveFile::open(path)?))
+    }
+}
@@ -0,0 +1,25 @@
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+
+// First line of a block archive, describes what it contains.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ArchiveHeader {
+    pub chain_id: String,
+    pub origin_block: u64,
+    pub terminal_block: u64,
+    // Unix timestamp of when we started fetching the blocks
+    pub fetched_at: u64,
+    pub receipts: bool,
+}
+
+// Every other line of a block archive. We keep the block as returned by
+// `eth_getBlockByNumber` so no fields get lost.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ArchiveEntry {
+    pub block: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receipts: Option<Value>,
+}
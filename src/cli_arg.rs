@@ -1,12 +1,11 @@
 use clap::{
     Arg,
+    ArgAction,
     Command,
 };
 
 // This is not the recommended way to set clap args but it works and its too late to change it now
 pub fn create_match() -> clap::Command {
-    
-
     Command::new("sothis")
         .version("0.5.0")
         .author("makemake <vukasin@gostovic.me>")
@@ -15,19 +14,24 @@ pub fn create_match() -> clap::Command {
             .long("source_rpc")
             .short('s')
             .num_args(1..)
-            .required(true)
+            .required_unless_present("source_file")
             .help("HTTP JSON-RPC of the node we're querying data from"))
+        .arg(Arg::new("source_file")
+            .long("source_file")
+            .num_args(1..)
+            .help("Block archive or era1 file we're replaying blocks from, in place of source_rpc."))
         .arg(Arg::new("replay_rpc")
             .long("replay_rpc")
             .short('r')
             .num_args(1..)
-            .help("HTTP JSON-RPC of the node we're replaying data to"))
+            .action(ArgAction::Append)
+            .help("HTTP JSON-RPC of the node we're replaying data to. Pass several to replay to all of them in lockstep."))
         .arg(Arg::new("mode")
             .long("mode")
             .short('m')
             .num_args(1..)
             .default_value("historic")
-            .help("Choose between live, historic, track, fast_track, or call_track"))
+            .help("Choose between live, historic, mempool, track, fast_track, call_track, multi_track, or export"))
         .arg(Arg::new("terminal_block")
             .long("terminal_block")
             .short('b')
@@ -55,10 +59,69 @@ pub fn create_match() -> clap::Command {
             .num_args(1..)
             .default_value("0")
             .help("Default delay for block replay in ms"))
+        .arg(Arg::new("replay_speed")
+            .long("replay_speed")
+            .num_args(1..)
+            .help("Reproduce historical block times, sped up by this multiplier. Overrides replay_delay."))
         .arg(Arg::new("send_as_unsigned")
             .long("send_as_unsigned")
             .num_args(0..)
             .help("Exit the program if a transaction fails"))
+        .arg(Arg::new("preserve_order")
+            .long("preserve_order")
+            .num_args(0..)
+            .help("Verify that replayed blocks keep the historical transaction order."))
+        .arg(Arg::new("key_file")
+            .long("key_file")
+            .num_args(1..)
+            .help("JSON file mapping historical senders to private keys used to re-sign transactions."))
+        .arg(Arg::new("key_seed")
+            .long("key_seed")
+            .num_args(1..)
+            .help("Seed used to derive keys for re-signing transactions of senders not in the key file."))
+        .arg(Arg::new("hash_map_file")
+            .long("hash_map_file")
+            .num_args(1..)
+            .default_value("tx_hash_map.csv")
+            .help("File we write original to re-signed transaction hashes to."))
+        .arg(Arg::new("remediate")
+            .long("remediate")
+            .num_args(0..)
+            .help("Patch sender balances and nonces with cheat RPCs and resend transactions that failed."))
+        .arg(Arg::new("rewrite_file")
+            .long("rewrite_file")
+            .num_args(1..)
+            .help("JSON file with rules to rewrite matching transactions and swap contract code while replaying."))
+        .arg(Arg::new("report")
+            .long("report")
+            .num_args(1..)
+            .help("Write failed transactions and entropy stats to this file. JSON, or CSV if it ends with .csv."))
+        .arg(Arg::new("mine_with_source")
+            .long("mine_with_source")
+            .num_args(0..)
+            .help("In mempool mode, mine a block on the replay node every time the source gets one."))
+        .arg(Arg::new("divergence_report")
+            .long("divergence_report")
+            .num_args(1..)
+            .help("Write blocks where the replay nodes mined different results to this JSON file."))
+        .arg(Arg::new("spawn_anvil")
+            .long("spawn_anvil")
+            .num_args(0..)
+            .help("Spawn an anvil fork of source_rpc to replay to, instead of using replay_rpc."))
+        .arg(Arg::new("anvil_path")
+            .long("anvil_path")
+            .num_args(1..)
+            .default_value("anvil")
+            .help("Path to the anvil binary used by spawn_anvil."))
+        .arg(Arg::new("anvil_log")
+            .long("anvil_log")
+            .num_args(1..)
+            .default_value("anvil.log")
+            .help("File we write the logs of the spawned anvil to."))
+        .arg(Arg::new("setup_file")
+            .long("setup_file")
+            .num_args(1..)
+            .help("JSON file with contracts, balances, code and storage to set up on the replay node instead of prompting."))
         .arg(Arg::new("no_setup")
             .long("no_setup")
             .num_args(0..)
@@ -66,7 +129,12 @@ pub fn create_match() -> clap::Command {
         .arg(Arg::new("decimal")
             .long("decimal")
             .num_args(0..)
-            .help("Return decimal values, not hex."))
+            .help("Return decimal values, not hex. Same as --number_format decimal."))
+        .arg(Arg::new("number_format")
+            .long("number_format")
+            .num_args(1..)
+            .conflicts_with("decimal")
+            .help("How tracked values are written: hex, decimal, signed, fixed:{decimals} or signed_fixed:{decimals}."))
         .arg(Arg::new("contract_address")
             .long("contract_address")
             .short('c')
@@ -78,26 +146,95 @@ pub fn create_match() -> clap::Command {
             .long("storage_slot")
             .short('l')
             .num_args(1..)
-            .required_if_eq("mode", "track")
-            .required_if_eq("mode", "fast_track")
+            .conflicts_with("variable")
             .help("Storage slot for the variable we're tracking"))
+        .arg(Arg::new("storage_layout")
+            .long("storage_layout")
+            .num_args(1..)
+            .help("solc/forge storageLayout JSON (or an artifact containing one) used to resolve --variable."))
+        .arg(Arg::new("variable")
+            .long("variable")
+            .num_args(1..)
+            .requires("storage_layout")
+            .help("Variable to track instead of --storage_slot, e.g. `balances[0xabc...]` or `config.fee`."))
+        .arg(Arg::new("fields")
+            .long("fields")
+            .num_args(1..)
+            .help("Fields packed in the tracked slot to decode, as comma separated name:offset:size:type."))
+        .arg(Arg::new("watch_fields")
+            .long("watch_fields")
+            .num_args(1..)
+            .help("Comma separated fields or return values whose changes are recorded, defaults to all of them."))
         .arg(Arg::new("calldata")
             .long("calldata")
             .short('a')
             .num_args(1..)
-            .required_if_eq("mode", "call_track")
-            .help("Storage slot for the variable we're tracking"))
+            .conflicts_with("signature")
+            .help("Calldata for the eth_call we're tracking."))
+        .arg(Arg::new("signature")
+            .long("signature")
+            .num_args(1..)
+            .help("Signature with return types to encode the call with, e.g. `latestRoundData()(uint80,int256 answer,uint256,uint256,uint80)`."))
+        .arg(Arg::new("args")
+            .long("args")
+            .num_args(1..)
+            .requires("signature")
+            .help("Arguments of --signature, arrays like [0xabc,0xdef]."))
+        .arg(Arg::new("spec_file")
+            .long("spec_file")
+            .num_args(1..)
+            .required_if_eq("mode", "multi_track")
+            .help("JSON file with the slots and calls to track in multi_track mode."))
         .arg(Arg::new("origin_block")
             .long("origin_block")
             .short('o')
             .num_args(1..)
             .required_if_eq("mode", "fast_track")
+            .required_if_eq("mode", "multi_track")
+            .required_if_eq("mode", "export")
             .help("First block sothis will look at."))
         .arg(Arg::new("query_interval")
             .long("query_interval")
             .short('q')
             .num_args(1..)
             .help("Interval spacing of blocks to query."))
+        .arg(Arg::new("concurrency")
+            .long("concurrency")
+            .num_args(1..)
+            .default_value("1")
+            .help("Number of block chunks fast_track and call_track fetch at the same time."))
+        .arg(Arg::new("chunk_size")
+            .long("chunk_size")
+            .num_args(1..)
+            .default_value("100")
+            .help("Number of blocks in each chunk fetched with --concurrency."))
+        .arg(Arg::new("search")
+            .long("search")
+            .num_args(1..)
+            .default_value("scan")
+            .help("How fast_track looks for changes: scan every block, or bisect sampled intervals."))
+        .arg(Arg::new("sample_interval")
+            .long("sample_interval")
+            .num_args(1..)
+            .default_value("1000")
+            .help("Blocks between the samples of --search bisect."))
+        .arg(Arg::new("confirm_with_traces")
+            .long("confirm_with_traces")
+            .num_args(0..)
+            .help("Check the blocks the contract was called in with trace_filter when bisecting, to catch changes that got reverted."))
+        .arg(Arg::new("resume")
+            .long("resume")
+            .num_args(0..)
+            .conflicts_with("decimal")
+            .help("Continue tracking after the last change in the output file."))
+        .arg(Arg::new("annotate")
+            .long("annotate")
+            .num_args(0..)
+            .help("Add the block timestamp and the transactions that made the change to tracked changes."))
+        .arg(Arg::new("with_receipts")
+            .long("with_receipts")
+            .num_args(0..)
+            .help("Include transaction receipts when exporting blocks."))
         .arg(Arg::new("path")
             .long("path")
             .short('p')
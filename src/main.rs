@@ -1,17 +1,31 @@
+mod archive;
 mod cli_arg;
 mod replay;
 mod rpc;
 mod tracker;
 
-use ethers::types::U256;
+use crate::archive::export::export_blocks;
+use crate::archive::source::{
+    open_block_file,
+    BlockSource,
+};
 
+use crate::replay::anvil::AnvilInstance;
+use crate::replay::mempool::mirror_mempool;
 use crate::replay::replay::replay_historic_blocks;
 use crate::replay::replay::replay_live;
 use crate::replay::setup::contract_setup;
+use crate::replay::types::ReplayConfig;
 
 use crate::tracker::call_track::call_track;
+use crate::tracker::decoder::StateDecoder;
 use crate::tracker::fast_track::fast_track_state;
+use crate::tracker::format::NumberFormat;
+use crate::tracker::layout::resolve_storage_slot;
+use crate::tracker::multi_track::multi_track;
+use crate::tracker::signature::resolve_calldata;
 use crate::tracker::tracker::track_state;
+use crate::tracker::types::SearchStrategy;
 
 use crate::rpc::format::format_number_input;
 use crate::rpc::format::hex_to_decimal;
@@ -21,11 +35,10 @@ use rpc::rpc::RpcConnection;
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = cli_arg::create_match().get_matches();
 
+    // Historic replay can read blocks from a file instead, so this is optional
     let source_rpc = matches
         .get_one::<String>("source_rpc")
-        .expect("Invalid source_rpc")
-        .to_string();
-    let source_rpc = RpcConnection::new(source_rpc);
+        .map(|url| RpcConnection::new(url.to_string()));
 
     let mode: String = matches
         .get_one::<String>("mode")
@@ -35,11 +48,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "historic" => {
             println!("Replaying in historic mode...");
 
-            let replay_rpc: String = matches
-                .get_one::<String>("replay_rpc")
-                .expect("Invalid replay_rpc")
-                .to_string();
-            let replay_rpc = RpcConnection::new(replay_rpc);
+            // Either spawn our own fork to replay to, or use the one we're given
+            let (_anvil, replay_rpcs) = match matches
+                .get_occurrences::<String>("spawn_anvil")
+                .is_some()
+            {
+                true => {
+                    // Replaying starts after the block the replay node is at, so forking
+                    // from the latest block would leave nothing to replay
+                    let fork_block = matches
+                        .get_one::<String>("origin_block")
+                        .ok_or("--spawn_anvil in historic mode needs --origin_block to fork from")?
+                        .parse::<u64>()?;
+                    let (anvil, replay_rpc) =
+                        spawn_anvil(&matches, source_rpc.as_ref(), Some(fork_block)).await?;
+                    (Some(anvil), vec![replay_rpc])
+                }
+                false => {
+                    (
+                        None,
+                        matches
+                            .get_many::<String>("replay_rpc")
+                            .expect("Invalid replay_rpc")
+                            .map(|url| RpcConnection::new(url.to_string()))
+                            .collect(),
+                    )
+                }
+            };
 
             let terminal_block: String = matches
                 .get_one::<String>("terminal_block")
@@ -61,31 +96,88 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .get_one::<String>("replay_delay")
                 .expect("required")
                 .parse::<u64>()?;
+            let replay_speed = matches
+                .get_one::<String>("replay_speed")
+                .map(|x| {
+                    x.parse::<f64>()
+                        .map_err(|e| format!("Invalid --replay_speed {}: {}", x, e))
+                })
+                .transpose()?;
+            let preserve_order = matches
+                .get_occurrences::<String>("preserve_order")
+                .is_some();
+            let key_file = matches.get_one::<String>("key_file").cloned();
+            let key_seed = matches.get_one::<String>("key_seed").cloned();
+            let hash_map_file = matches
+                .get_one::<String>("hash_map_file")
+                .expect("Invalid hash_map_file")
+                .to_string();
+            let remediate = matches.get_occurrences::<String>("remediate").is_some();
+            let report_file = matches.get_one::<String>("report").cloned();
+            let divergence_file = matches.get_one::<String>("divergence_report").cloned();
+            let rewrite_file = matches.get_one::<String>("rewrite_file").cloned();
 
+            let setup_file = matches.get_one::<String>("setup_file").cloned();
             let no_setup = matches.get_occurrences::<String>("no_setup").is_some();
-            if !no_setup {
-                contract_setup(replay_rpc.clone()).await?;
+            if !no_setup || setup_file.is_some() {
+                contract_setup(&replay_rpcs, setup_file).await?;
             }
 
-            replay_historic_blocks(
-                source_rpc,
-                replay_rpc,
-                hex_to_decimal(&terminal_block)?,
+            let config = ReplayConfig {
                 replay_delay,
+                replay_speed,
                 entropy_threshold,
                 exit_on_tx_fail,
                 send_as_unsigned,
+                preserve_order,
+                key_file,
+                key_seed,
+                hash_map_file,
+                remediate,
+                report_file,
+                rewrite_file,
+                divergence_file,
+            };
+
+            let mut source: Box<dyn BlockSource> = match matches.get_one::<String>("source_file") {
+                Some(source_file) => open_block_file(source_file)?,
+                None => Box::new(source_rpc.expect("No source_rpc or source_file set!")),
+            };
+
+            replay_historic_blocks(
+                source.as_mut(),
+                replay_rpcs,
+                hex_to_decimal(&terminal_block)?,
+                config,
             )
             .await?;
         }
         "live" => {
             println!("Replaying live blocks...");
-
-            let replay_rpc: String = matches
-                .get_one::<String>("replay_rpc")
-                .expect("Invalid replay_rpc supplied!")
-                .to_string();
-            let replay_rpc = RpcConnection::new(replay_rpc);
+            // Either spawn our own fork to replay to, or use the one we're given
+            let (_anvil, replay_rpcs) =
+                match matches.get_occurrences::<String>("spawn_anvil").is_some() {
+                    true => {
+                        let fork_block = matches
+                            .get_one::<String>("origin_block")
+                            .map(|x| x.parse::<u64>())
+                            .transpose()?;
+                        let (anvil, replay_rpc) =
+                            spawn_anvil(&matches, source_rpc.as_ref(), fork_block).await?;
+                        (Some(anvil), vec![replay_rpc])
+                    }
+                    false => {
+                        (
+                            None,
+                            matches
+                                .get_many::<String>("replay_rpc")
+                                .expect("Invalid replay_rpc supplied!")
+                                .map(|url| RpcConnection::new(url.to_string()))
+                                .collect(),
+                        )
+                    }
+                };
+            let source_rpc = source_rpc.expect("Invalid source_rpc");
 
             let entropy_threshold = matches
                 .get_one::<String>("entropy_threshold")
@@ -105,36 +197,115 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .get_one::<String>("block_listen_time")
                 .expect("Invalid block_listen_time")
                 .parse::<u64>()?;
+            let replay_speed = matches
+                .get_one::<String>("replay_speed")
+                .map(|x| {
+                    x.parse::<f64>()
+                        .map_err(|e| format!("Invalid --replay_speed {}: {}", x, e))
+                })
+                .transpose()?;
+            let preserve_order = matches
+                .get_occurrences::<String>("preserve_order")
+                .is_some();
+            let key_file = matches.get_one::<String>("key_file").cloned();
+            let key_seed = matches.get_one::<String>("key_seed").cloned();
+            let hash_map_file = matches
+                .get_one::<String>("hash_map_file")
+                .expect("Invalid hash_map_file")
+                .to_string();
+            let remediate = matches.get_occurrences::<String>("remediate").is_some();
+            let report_file = matches.get_one::<String>("report").cloned();
+            let divergence_file = matches.get_one::<String>("divergence_report").cloned();
+            let rewrite_file = matches.get_one::<String>("rewrite_file").cloned();
 
+            let setup_file = matches.get_one::<String>("setup_file").cloned();
             let no_setup = matches.get_occurrences::<String>("no_setup").is_some();
-            if !no_setup {
-                contract_setup(replay_rpc.clone()).await?;
+            if !no_setup || setup_file.is_some() {
+                contract_setup(&replay_rpcs, setup_file).await?;
             }
 
-            replay_live(
-                source_rpc,
-                replay_rpc,
+            let config = ReplayConfig {
                 replay_delay,
-                block_listen_time,
+                replay_speed,
                 entropy_threshold,
                 exit_on_tx_fail,
                 send_as_unsigned,
+                preserve_order,
+                key_file,
+                key_seed,
+                hash_map_file,
+                remediate,
+                report_file,
+                rewrite_file,
+                divergence_file,
+            };
+
+            replay_live(source_rpc, replay_rpcs, block_listen_time, config).await?;
+        }
+        "mempool" => {
+            println!("Mirroring pending transactions...");
+            let source_rpc = source_rpc.expect("Invalid source_rpc");
+
+            // Either spawn our own fork to mirror to, or use the one we're given
+            let (_anvil, replay_rpc) =
+                match matches.get_occurrences::<String>("spawn_anvil").is_some() {
+                    true => {
+                        let (anvil, replay_rpc) =
+                            spawn_anvil(&matches, Some(&source_rpc), None).await?;
+                        (Some(anvil), replay_rpc)
+                    }
+                    false => {
+                        let replay_rpcs: Vec<&String> = matches
+                            .get_many::<String>("replay_rpc")
+                            .expect("Invalid replay_rpc")
+                            .collect();
+                        if replay_rpcs.len() > 1 {
+                            return Err("Mempool mode mirrors to a single replay_rpc".into());
+                        }
+                        (None, RpcConnection::new(replay_rpcs[0].to_string()))
+                    }
+                };
+
+            let block_listen_time = matches
+                .get_one::<String>("block_listen_time")
+                .expect("Invalid block_listen_time")
+                .parse::<u64>()?;
+            let mine_with_source = matches
+                .get_occurrences::<String>("mine_with_source")
+                .is_some();
+            let send_as_unsigned = matches
+                .get_occurrences::<String>("send_as_unsigned")
+                .is_some();
+
+            mirror_mempool(
+                source_rpc,
+                replay_rpc,
+                block_listen_time,
+                mine_with_source,
+                send_as_unsigned,
             )
             .await?;
         }
         "track" => {
             println!("Tracking state variable...");
+            let source_rpc = source_rpc.expect("Invalid source_rpc");
             println!("Send SIGTERM or SIGINT (ctrl-c) to serialize to JSON, write and stop.");
 
             let contract_address: String = matches
                 .get_one::<String>("contract_address")
                 .expect("Invalid contract_address")
                 .to_string();
-            let storage_slot: String = matches
-                .get_one::<String>("storage_slot")
-                .expect("Invalid storage_slot")
-                .to_string();
-            let storage_slot = U256::from_dec_str(&storage_slot)?;
+            let (storage_slot, variable) = resolve_storage_slot(
+                matches.get_one::<String>("storage_slot"),
+                matches.get_one::<String>("storage_layout"),
+                matches.get_one::<String>("variable"),
+            )?;
+            let decoder = StateDecoder::from_args(
+                variable,
+                None,
+                matches.get_one::<String>("fields"),
+                matches.get_one::<String>("watch_fields"),
+            )?;
 
             // If terminal_block is set by the user use that, otherwise have it be none
             let terminal_block: Option<u64> = matches
@@ -149,7 +320,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .get_one::<String>("block_listen_time")
                 .expect("required")
                 .parse::<u64>()?;
-            let decimal = matches.get_occurrences::<String>("decimal").is_some();
+            let number_format = number_format(&matches)?;
             let path = matches
                 .get_one::<String>("path")
                 .expect("required")
@@ -158,32 +329,47 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .get_one::<String>("filename")
                 .expect("required")
                 .to_string();
+            let resume = matches.get_occurrences::<String>("resume").is_some();
+            let annotate = matches.get_occurrences::<String>("annotate").is_some();
+            if resume && filename.is_empty() {
+                return Err("--resume needs the --filename to continue".into());
+            }
 
             track_state(
                 source_rpc,
                 storage_slot,
+                decoder,
                 contract_address,
                 terminal_block,
                 block_listen_time,
-                decimal,
+                number_format,
                 path,
                 filename,
+                resume,
+                annotate,
             )
             .await?;
         }
         "fast_track" => {
             println!("Fast tracking state variable...");
+            let source_rpc = source_rpc.expect("Invalid source_rpc");
             println!("Send SIGTERM or SIGINT (ctrl-c) to serialize to JSON, write and stop.");
 
             let contract_address: String = matches
                 .get_one::<String>("contract_address")
                 .expect("Invalid contract_address")
                 .to_string();
-            let storage_slot: String = matches
-                .get_one::<String>("storage_slot")
-                .expect("Invalid storage_slot")
-                .to_string();
-            let storage_slot = U256::from_dec_str(&storage_slot)?;
+            let (storage_slot, variable) = resolve_storage_slot(
+                matches.get_one::<String>("storage_slot"),
+                matches.get_one::<String>("storage_layout"),
+                matches.get_one::<String>("variable"),
+            )?;
+            let decoder = StateDecoder::from_args(
+                variable,
+                None,
+                matches.get_one::<String>("fields"),
+                matches.get_one::<String>("watch_fields"),
+            )?;
 
             // If terminal_block is set by the user use that, otherwise have it be none
             let terminal_block = matches
@@ -197,7 +383,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let query_interval = matches
                 .get_one::<String>("query_interval")
                 .map(|x| x.parse().expect("Invalid query interval"));
-            let decimal = matches.get_occurrences::<String>("decimal").is_some();
+            let search = match matches
+                .get_one::<String>("search")
+                .expect("Invalid search")
+                .as_str()
+            {
+                "scan" => SearchStrategy::Scan,
+                "bisect" => {
+                    if query_interval.is_some() {
+                        return Err("--query_interval can't be used with --search bisect, use --sample_interval".into());
+                    }
+                    SearchStrategy::Bisect {
+                        sample_interval: matches
+                            .get_one::<String>("sample_interval")
+                            .expect("Invalid sample_interval")
+                            .parse::<u64>()?,
+                        confirm_with_traces: matches
+                            .get_occurrences::<String>("confirm_with_traces")
+                            .is_some(),
+                    }
+                }
+                _ => return Err("Invalid search, use scan or bisect".into()),
+            };
+            let (concurrency, chunk_size) = fetch_options(&matches)?;
+            let number_format = number_format(&matches)?;
             let path = matches
                 .get_one::<String>("path")
                 .expect("Invalid path")
@@ -206,32 +415,55 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .get_one::<String>("filename")
                 .expect("Invalid filename")
                 .to_string();
+            let resume = matches.get_occurrences::<String>("resume").is_some();
+            let annotate = matches.get_occurrences::<String>("annotate").is_some();
+            if resume && filename.is_empty() {
+                return Err("--resume needs the --filename to continue".into());
+            }
 
             fast_track_state(
                 source_rpc,
                 storage_slot,
+                decoder,
+                search,
                 contract_address,
                 terminal_block,
                 origin_block,
                 query_interval,
-                decimal,
+                concurrency,
+                chunk_size,
+                number_format,
                 path,
                 filename,
+                resume,
+                annotate,
             )
             .await?;
         }
         "call_track" => {
             println!("Call tracking...");
+            let source_rpc = source_rpc.expect("Invalid source_rpc");
             println!("Send SIGTERM or SIGINT (ctrl-c) to serialize to JSON, write and stop.");
 
             let contract_address: String = matches
                 .get_one::<String>("contract_address")
                 .expect("Invalid contract_address")
                 .to_string();
-            let calldata: String = matches
-                .get_one::<String>("calldata")
-                .expect("Invalid calldata")
-                .to_string();
+            let args: Vec<String> = matches
+                .get_many::<String>("args")
+                .map(|args| args.cloned().collect())
+                .unwrap_or_default();
+            let (calldata, signature) = resolve_calldata(
+                matches.get_one::<String>("calldata"),
+                matches.get_one::<String>("signature"),
+                &args,
+            )?;
+            let decoder = StateDecoder::from_args(
+                None,
+                signature,
+                matches.get_one::<String>("fields"),
+                matches.get_one::<String>("watch_fields"),
+            )?;
 
             // If terminal_block is set by the user use that, otherwise have it be none
             let terminal_block = matches
@@ -245,7 +477,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let query_interval = matches
                 .get_one::<String>("query_interval")
                 .map(|x| x.parse().expect("Invalid query interval"));
-            let decimal = matches.get_occurrences::<String>("decimal").is_some();
+            let (concurrency, chunk_size) = fetch_options(&matches)?;
+            let number_format = number_format(&matches)?;
             let path = matches
                 .get_one::<String>("path")
                 .expect("Invalid path")
@@ -254,15 +487,111 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .get_one::<String>("filename")
                 .expect("Invalid filename")
                 .to_string();
+            let resume = matches.get_occurrences::<String>("resume").is_some();
+            let annotate = matches.get_occurrences::<String>("annotate").is_some();
+            if resume && filename.is_empty() {
+                return Err("--resume needs the --filename to continue".into());
+            }
 
             call_track(
                 source_rpc,
                 calldata,
+                decoder,
                 contract_address,
                 terminal_block,
                 origin_block,
                 query_interval,
-                decimal,
+                concurrency,
+                chunk_size,
+                number_format,
+                path,
+                filename,
+                resume,
+                annotate,
+            )
+            .await?;
+        }
+        "multi_track" => {
+            println!("Tracking multiple targets...");
+            let source_rpc = source_rpc.expect("Invalid source_rpc");
+            println!("Send SIGTERM or SIGINT (ctrl-c) to serialize to JSON, write and stop.");
+
+            let spec_file: String = matches
+                .get_one::<String>("spec_file")
+                .expect("Invalid spec_file")
+                .to_string();
+
+            // If terminal_block is set by the user use that, otherwise have it be none
+            let terminal_block = matches
+                .get_one::<String>("terminal_block")
+                .map(|x| x.parse().expect("Invalid terminal block"));
+
+            let origin_block = matches
+                .get_one::<String>("origin_block")
+                .expect("Invalid origin_block")
+                .parse::<u64>()?;
+            let query_interval = matches
+                .get_one::<String>("query_interval")
+                .map(|x| x.parse().expect("Invalid query interval"));
+            let (concurrency, chunk_size) = fetch_options(&matches)?;
+            let number_format = number_format(&matches)?;
+            let path = matches
+                .get_one::<String>("path")
+                .expect("Invalid path")
+                .to_string();
+            let filename = matches
+                .get_one::<String>("filename")
+                .expect("Invalid filename")
+                .to_string();
+            let resume = matches.get_occurrences::<String>("resume").is_some();
+            let annotate = matches.get_occurrences::<String>("annotate").is_some();
+
+            multi_track(
+                source_rpc,
+                spec_file,
+                terminal_block,
+                origin_block,
+                query_interval,
+                concurrency,
+                chunk_size,
+                number_format,
+                path,
+                filename,
+                resume,
+                annotate,
+            )
+            .await?;
+        }
+        "export" => {
+            println!("Exporting blocks...");
+            let source_rpc = source_rpc.expect("Invalid source_rpc");
+            println!("Send SIGTERM or SIGINT (ctrl-c) to stop and finish writing the archive.");
+
+            let origin_block = matches
+                .get_one::<String>("origin_block")
+                .expect("Invalid origin_block")
+                .parse::<u64>()?;
+
+            // If terminal_block is set by the user use that, otherwise have it be none
+            let terminal_block = matches
+                .get_one::<String>("terminal_block")
+                .map(|x| x.parse().expect("Invalid terminal block"));
+
+            let with_receipts = matches.get_occurrences::<String>("with_receipts").is_some();
+            let path = matches
+                .get_one::<String>("path")
+                .expect("Invalid path")
+                .to_string();
+            let filename = matches
+                .get_one::<String>("filename")
+                .expect("Invalid filename")
+                .to_string();
+
+            export_blocks(
+                source_rpc,
+                origin_block,
+                terminal_block,
+                with_receipts,
                 path,
                 filename,
             )
@@ -275,3 +604,53 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+// Spawn an anvil fork of `source_rpc` at `fork_block` (or its latest block) and return
+// it with a connection to it. Anvil is stopped when the returned instance is dropped.
+async fn spawn_anvil(
+    matches: &clap::ArgMatches,
+    source_rpc: Option<&RpcConnection>,
+    fork_block: Option<u64>,
+) -> Result<(AnvilInstance, RpcConnection), Box<dyn std::error::Error>> {
+    let fork_url = source_rpc
+        .ok_or("--spawn_anvil needs a source_rpc to fork from")?
+        .url();
+    let anvil = AnvilInstance::spawn(
+        matches
+            .get_one::<String>("anvil_path")
+            .expect("Invalid anvil_path"),
+        fork_url,
+        fork_block,
+        matches
+            .get_occurrences::<String>("preserve_order")
+            .is_some(),
+        matches
+            .get_one::<String>("anvil_log")
+            .expect("Invalid anvil_log"),
+    )
+    .await?;
+    let rpc = anvil.rpc();
+    Ok((anvil, rpc))
+}
+
+// Get the `--number_format`, or decimal with the older `--decimal` flag.
+fn number_format(matches: &clap::ArgMatches) -> Result<NumberFormat, Box<dyn std::error::Error>> {
+    match matches.get_one::<String>("number_format") {
+        Some(number_format) => NumberFormat::parse(number_format),
+        None if matches.get_occurrences::<String>("decimal").is_some() => Ok(NumberFormat::Decimal),
+        None => Ok(NumberFormat::Hex),
+    }
+}
+
+// Get how many chunks of how many blocks the historic tracking modes fetch at once.
+fn fetch_options(matches: &clap::ArgMatches) -> Result<(usize, u64), Box<dyn std::error::Error>> {
+    let concurrency = matches
+        .get_one::<String>("concurrency")
+        .expect("Invalid concurrency")
+        .parse::<usize>()?;
+    let chunk_size = matches
+        .get_one::<String>("chunk_size")
+        .expect("Invalid chunk_size")
+        .parse::<u64>()?;
+    Ok((concurrency, chunk_size))
+}
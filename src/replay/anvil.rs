@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::net::TcpListener;
+use std::process::{
+    Child,
+    Command,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use crate::RpcConnection;
+
+// How long we give anvil to fetch the fork and start answering requests.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+// A local anvil fork we spawned to replay to. It gets killed when dropped, or
+// when sothis gets interrupted.
+pub struct AnvilInstance {
+    child: Arc<Mutex<Child>>,
+    url: String,
+}
+
+impl AnvilInstance {
+    // Spawn anvil forking `fork_url` at `fork_block` on a free port, with its output
+    // going to `log_file`, and wait until it answers requests.
+    pub async fn spawn(
+        anvil_path: &str,
+        fork_url: &str,
+        fork_block: Option<u64>,
+        preserve_order: bool,
+        log_file: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Let the OS pick a free port for us. Something could grab it before anvil
+        // does, but then anvil fails to start and we tell the user.
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+
+        let log = File::create(log_file)?;
+        let mut command = Command::new(anvil_path);
+        command
+            .arg("--fork-url")
+            .arg(fork_url)
+            .arg("--port")
+            .arg(port.to_string())
+            .stdout(log.try_clone()?)
+            .stderr(log);
+        if let Some(fork_block) = fork_block {
+            command
+                .arg("--fork-block-number")
+                .arg(fork_block.to_string());
+        }
+        if preserve_order {
+            command.arg("--order").arg("fifo");
+        }
+
+        let child = command
+            .spawn()
+            .map_err(|e| format!("Could not start {}: {}", anvil_path, e))?;
+        println!(
+            "Started anvil on port {}, writing its logs to: {}",
+            port, log_file
+        );
+
+        let anvil = Self {
+            child: Arc::new(Mutex::new(child)),
+            url: format!("http://127.0.0.1:{}", port),
+        };
+
+        // Ctrl-C exits without running destructors, so kill anvil from the handler too
+        let child = anvil.child.clone();
+        ctrlc::set_handler(move || {
+            if let Ok(mut child) = child.lock() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            std::process::exit(130);
+        })?;
+
+        anvil.wait_until_healthy(log_file).await?;
+        Ok(anvil)
+    }
+
+    async fn wait_until_healthy(&self, log_file: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let rpc = self.rpc();
+        let start = Instant::now();
+
+        loop {
+            if let Some(status) = self.child.lock().unwrap().try_wait()? {
+                return Err(format!("anvil exited with {}, see {}", status, log_file).into());
+            }
+            if rpc.block_number().await.is_ok() {
+                println!("anvil is up at {}", self.url);
+                return Ok(());
+            }
+            if start.elapsed() > STARTUP_TIMEOUT {
+                return Err(format!(
+                    "anvil did not come up within {}s, see {}",
+                    STARTUP_TIMEOUT.as_secs(),
+                    log_file
+                )
+                .into());
+            }
+
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+    }
+
+    pub fn rpc(&self) -> RpcConnection {
+        RpcConnection::new(self.url.clone())
+    }
+}
+
+impl Drop for AnvilInstance {
+    fn drop(&mut self) {
+        if let Ok(mut child) = self.child.lock() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        println!("Stopped anvil");
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::archive::export::get_receipts;
+use crate::RpcConnection;
+
+// The parts of a receipt we expect every replay node to agree on.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReceiptSummary {
+    pub transaction_hash: String,
+    pub status: String,
+    pub gas_used: String,
+}
+
+// What a replay node mined for a historical block.
+#[derive(Debug, Clone, Serialize)]
+pub struct MinedBlock {
+    pub url: String,
+    pub tx_count: usize,
+    pub receipts: Vec<ReceiptSummary>,
+}
+
+impl MinedBlock {
+    // Get the latest block of `replay_rpc` and the receipts of its txs, in the order they were mined.
+    pub async fn latest(replay_rpc: &RpcConnection) -> Result<Self, Box<dyn std::error::Error>> {
+        let block: Value =
+            serde_json::from_str(&replay_rpc.get_block_by_number("latest".to_string()).await?)?;
+        let hex_block = block["number"].as_str().unwrap_or_default().to_string();
+
+        let receipts = get_receipts(replay_rpc, hex_block, &block).await?;
+        let receipts = receipts
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|receipt| {
+                ReceiptSummary {
+                    transaction_hash: receipt["transactionHash"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    status: receipt["status"].as_str().unwrap_or_default().to_string(),
+                    gas_used: receipt["gasUsed"].as_str().unwrap_or_default().to_string(),
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            url: replay_rpc.url().to_string(),
+            tx_count: block["transactions"].as_array().map_or(0, Vec::len),
+            receipts,
+        })
+    }
+
+    fn matches(&self, other: &MinedBlock) -> bool {
+        self.tx_count == other.tx_count && self.receipts == other.receipts
+    }
+}
+
+// A block where the replay nodes disagree on what got mined.
+#[derive(Debug, Clone, Serialize)]
+pub struct Divergence {
+    pub block_number: u64,
+    pub nodes: Vec<MinedBlock>,
+}
+
+// Blocks where replaying to several nodes gave different results.
+#[derive(Debug, Default, Serialize)]
+pub struct DivergenceReport {
+    pub blocks_compared: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+impl DivergenceReport {
+    // Compare what every node mined for `block_number`, and record it if they disagree.
+    pub fn check(&mut self, block_number: u64, nodes: Vec<MinedBlock>) {
+        // Replace the previous result if the block was replayed again after a reorg
+        self.divergences
+            .retain(|divergence| divergence.block_number != block_number);
+        self.blocks_compared += 1;
+
+        let diverged = nodes.windows(2).any(|pair| !pair[0].matches(&pair[1]));
+        if !diverged {
+            return;
+        }
+
+        println!(
+            "!!! \x1b[91mReplay nodes diverged at block {}!\x1b[0m !!!",
+            block_number
+        );
+        for node in &nodes {
+            let failed = node
+                .receipts
+                .iter()
+                .filter(|receipt| receipt.status != "0x1")
+                .count();
+            println!(
+                "    {}: {} txs mined, {} reverted",
+                node.url, node.tx_count, failed
+            );
+        }
+
+        self.divergences.push(Divergence {
+            block_number,
+            nodes,
+        });
+    }
+
+    pub fn print(&self) {
+        if self.divergences.is_empty() {
+            println!(
+                "All replay nodes agreed on the {} compared blocks",
+                self.blocks_compared
+            );
+        } else {
+            let blocks: Vec<u64> = self
+                .divergences
+                .iter()
+                .map(|divergence| divergence.block_number)
+                .collect();
+            println!(
+                "!!! \x1b[91mReplay nodes diverged in {} block(s):\x1b[0m {:?} !!!",
+                blocks.len(),
+                blocks
+            );
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        println!("Writing divergence report to: {}", path);
+        fs::write(path, serde_json::to_string(&self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+use std::future::Future;
+
+use crate::rpc::error::RequestError;
+use crate::rpc::types::BlockResult;
+use crate::RpcConnection;
+
+// Mirrors the header fields of historical blocks onto the replay node.
+// Not every node implements all of the cheat RPCs we use, so once one of
+// them fails we warn about it and stop calling it for the rest of the run.
+#[derive(Default)]
+pub struct HeaderMirror {
+    unsupported: HashSet<&'static str>,
+}
+
+impl HeaderMirror {
+    // Set the coinbase, base fee, gas limit and prevrandao of the next block
+    // to the ones of `block`.
+    pub async fn set_next_block_header(&mut self, replay_rpc: &RpcConnection, block: &BlockResult) {
+        self.try_set(
+            "anvil_setCoinbase",
+            replay_rpc.anvil_set_coinbase(block.miner.clone()),
+        )
+        .await;
+
+        // Pre-London blocks do not have a base fee
+        if let Some(base_fee) = &block.baseFeePerGas {
+            self.try_set(
+                "anvil_setNextBlockBaseFeePerGas",
+                replay_rpc.anvil_set_next_block_base_fee_per_gas(base_fee.clone()),
+            )
+            .await;
+        }
+
+        self.try_set(
+            "evm_setBlockGasLimit",
+            replay_rpc.evm_set_block_gas_limit(block.gasLimit.clone()),
+        )
+        .await;
+
+        // `mixHash` only holds prevrandao after the merge, where difficulty is always 0
+        let is_post_merge = block
+            .difficulty
+            .as_ref()
+            .map(|difficulty| difficulty == "0x0")
+            .unwrap_or(true);
+        if is_post_merge {
+            self.try_set(
+                "anvil_setPrevRandao",
+                replay_rpc.anvil_set_prev_randao(block.mixHash.clone()),
+            )
+            .await;
+        }
+    }
+
+    async fn try_set(
+        &mut self,
+        method: &'static str,
+        request: impl Future<Output = Result<String, RequestError>>,
+    ) {
+        if self.unsupported.contains(method) {
+            return;
+        }
+
+        if let Err(e) = request.await {
+            println!(
+                "!!! \x1b[93m{} is not supported by the replay node, skipping it:\x1b[0m {} !!!",
+                method, e
+            );
+            self.unsupported.insert(method);
+        }
+    }
+}
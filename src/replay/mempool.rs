@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+
+use serde_json::Value;
+use tokio::time::{
+    sleep,
+    Duration,
+};
+
+use crate::replay::types::MAX_POLL_RETRIES;
+use crate::rpc::format::*;
+use crate::rpc::types::*;
+use crate::RpcConnection;
+
+// Where we get pending txs of the source node from.
+enum PendingSource {
+    // Poll the whole mempool with `txpool_content`, supported by geth, reth, erigon and anvil
+    TxPool,
+    // Poll the hashes of new pending txs from a filter, and get the txs one by one
+    Filter(String),
+}
+
+impl PendingSource {
+    async fn new(source_rpc: &RpcConnection) -> Result<Self, Box<dyn std::error::Error>> {
+        if source_rpc.txpool_content().await.is_ok() {
+            println!("Mirroring pending transactions from `txpool_content`");
+            return Ok(PendingSource::TxPool);
+        }
+
+        let filter_id = source_rpc
+            .new_pending_transaction_filter()
+            .await
+            .map_err(|e| {
+                format!(
+                    "Source node supports neither `txpool_content` nor pending transaction filters: {}",
+                    e
+                )
+            })?;
+        println!("Source node does not support `txpool_content`, mirroring pending transactions from a filter");
+        Ok(PendingSource::Filter(filter_id))
+    }
+
+    // Get the txs that entered the mempool since the last poll.
+    //
+    // `txpool_content` always returns the whole mempool, so we remember what we saw in the
+    // last poll in `seen`, and forget txs once they leave the mempool.
+    async fn poll(
+        &self,
+        source_rpc: &RpcConnection,
+        seen: &mut HashSet<String>,
+    ) -> Result<Vec<Transaction>, Box<dyn std::error::Error>> {
+        let mut new_txs = Vec::new();
+
+        match self {
+            PendingSource::TxPool => {
+                let content = source_rpc.txpool_content().await?;
+                let mut pending = HashSet::new();
+
+                if let Some(senders) = content["pending"].as_object() {
+                    for txs in senders.values() {
+                        let mut txs: Vec<&Value> = match txs.as_object() {
+                            Some(txs) => txs.values().collect(),
+                            None => continue,
+                        };
+                        // Keep the txs of each sender in nonce order so they don't get rejected
+                        txs.sort_by_key(|tx| {
+                            hex_to_decimal(tx["nonce"].as_str().unwrap_or("0x0"))
+                                .unwrap_or_default()
+                        });
+
+                        for tx in txs {
+                            let hash = tx["hash"].as_str().unwrap_or_default().to_string();
+                            if !seen.contains(&hash) {
+                                if let Some(tx) = pending_transaction(tx.clone()) {
+                                    new_txs.push(tx);
+                                }
+                            }
+                            pending.insert(hash);
+                        }
+                    }
+                }
+
+                *seen = pending;
+            }
+            PendingSource::Filter(filter_id) => {
+                for hash in source_rpc.get_filter_changes(filter_id.clone()).await? {
+                    // The filter won't return these hashes again, so don't let one tx
+                    // failing to load lose the rest
+                    let tx = match source_rpc.get_transaction_by_hash(hash.clone()).await {
+                        Ok(tx) => serde_json::from_str::<Value>(&tx)?,
+                        Err(e) => {
+                            println!(
+                                "!!! \x1b[93mWARNING:\x1b[0m Could not get pending transaction {}: {} !!!",
+                                hash, e
+                            );
+                            continue;
+                        }
+                    };
+                    // The tx could've been mined or dropped in the meantime
+                    if tx.is_null() || !tx["blockHash"].is_null() {
+                        continue;
+                    }
+                    if let Some(tx) = pending_transaction(tx) {
+                        new_txs.push(tx);
+                    }
+                }
+            }
+        }
+
+        Ok(new_txs)
+    }
+}
+
+// Pending txs aren't in a block yet, so they have no block hash, number or index.
+// Txs we can't parse are skipped with a warning, so one odd tx doesn't stop mirroring.
+fn pending_transaction(mut tx: Value) -> Option<Transaction> {
+    for field in ["blockHash", "blockNumber", "transactionIndex"] {
+        if tx[field].is_null() {
+            tx[field] = Value::String("0x0".to_string());
+        }
+    }
+
+    let hash = tx["hash"].as_str().unwrap_or_default().to_string();
+    match serde_json::from_value(tx) {
+        Ok(tx) => Some(tx),
+        Err(e) => {
+            println!(
+                "!!! \x1b[93mWARNING:\x1b[0m Skipping malformed pending transaction {}: {} !!!",
+                hash, e
+            );
+            None
+        }
+    }
+}
+
+// To mirror the mempool we:
+// 0) Make sure that the chainids match
+// 1) Poll the mempool of the source node every `poll_interval` ms
+// 2) Forward every new pending tx to the mempool of the replay node
+// 3) If `mine_with_source` is set, mine a block on the replay node every time
+//    the source gets one, with the same timestamp
+pub async fn mirror_mempool(
+    source_rpc: RpcConnection,
+    replay_rpc: RpcConnection,
+    poll_interval: u64,
+    mine_with_source: bool,
+    send_as_unsigned: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let chain_id = source_rpc.chain_id().await?;
+    if chain_id != replay_rpc.chain_id().await? {
+        return Err("Chain IDs don't match".into());
+    }
+    let chain_id = hex_to_decimal(&chain_id)?;
+
+    if mine_with_source {
+        // set automine to false
+        replay_rpc.evm_set_automine(false).await?;
+        // set insanely high interval for the blocks
+        replay_rpc.evm_set_interval_mining(u32::MAX.into()).await?;
+    }
+
+    let pending_source = PendingSource::new(&source_rpc).await?;
+    let mut seen = HashSet::new();
+    let mut source_block = hex_to_decimal(&source_rpc.block_number().await?)?;
+    let (mut forwarded, mut failed) = (0, 0);
+    // Polls in a row that failed
+    let mut failed_polls = 0;
+
+    loop {
+        let polled = async {
+            let new_txs = pending_source.poll(&source_rpc, &mut seen).await?;
+            let latest_block = hex_to_decimal(&source_rpc.block_number().await?)?;
+            Ok::<_, Box<dyn std::error::Error>>((new_txs, latest_block))
+        }
+        .await;
+        let (new_txs, latest_block) = match polled {
+            Ok(polled) => {
+                failed_polls = 0;
+                polled
+            }
+            Err(e) => {
+                failed_polls += 1;
+                if failed_polls > MAX_POLL_RETRIES {
+                    return Err(format!(
+                        "Polling the source failed {} times in a row: {}",
+                        failed_polls, e
+                    )
+                    .into());
+                }
+                println!(
+                    "!!! \x1b[93mWARNING:\x1b[0m Polling the source failed, retrying: {} !!!",
+                    e
+                );
+                sleep(Duration::from_secs(1 << failed_polls)).await;
+                continue;
+            }
+        };
+
+        for tx in new_txs {
+            let hash = tx.hash.clone();
+            match replay_rpc.send(tx, chain_id, send_as_unsigned).await {
+                Ok(_) => forwarded += 1,
+                Err(e) => {
+                    // Txs failing is normal here, the replay node can lag behind the source
+                    failed += 1;
+                    println!(
+                        "!!! \x1b[93mError forwarding transaction {}:\x1b[0m {} !!!",
+                        hash, e
+                    );
+                }
+            }
+        }
+
+        if latest_block > source_block {
+            println!(
+                "Source is at block {}, forwarded {} pending transactions so far, {} failed",
+                latest_block, forwarded, failed
+            );
+
+            if mine_with_source {
+                for block_number in source_block + 1..=latest_block {
+                    let block: BlockResult = serde_json::from_str(
+                        &source_rpc
+                            .get_block_by_number(decimal_to_hex(block_number))
+                            .await?,
+                    )?;
+                    replay_rpc
+                        .evm_set_next_block_timestamp(hex_to_decimal(&block.timestamp)?)
+                        .await?;
+                    replay_rpc.evm_mine().await?;
+                }
+            }
+
+            source_block = latest_block;
+        }
+
+        sleep(Duration::from_millis(poll_interval)).await;
+    }
+}
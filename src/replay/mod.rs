@@ -1,3 +1,16 @@
+pub mod anvil;
+pub mod divergence;
+pub mod header;
+pub mod mempool;
+pub mod ordering;
+pub mod pacing;
+pub mod remediate;
+#[allow(clippy::module_inception)]
 pub mod replay;
+pub mod report;
+pub mod resign;
+pub mod rewrite;
 pub mod send_transaction;
 pub mod setup;
+pub mod types;
+pub mod withdrawals;
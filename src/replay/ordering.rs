@@ -0,0 +1,80 @@
+use crate::rpc::types::BlockResult;
+use crate::RpcConnection;
+
+// Warn the user if the replay node orders its mempool by fees.
+// Anvil only keeps the insertion order when started with `--order fifo`.
+pub async fn check_transaction_order(replay_rpc: &RpcConnection) {
+    match replay_rpc.anvil_node_info().await {
+        Ok(info) => {
+            let order = info["transactionOrder"].as_str().unwrap_or("unknown");
+            if order != "fifo" {
+                println!(
+                    "!!! \x1b[93mWARNING:\x1b[0m Replay node orders transactions by `{}`, start anvil with `--order fifo` to keep the historical order !!!",
+                    order
+                );
+            }
+        }
+        Err(_) => {
+            println!("!!! \x1b[93mWARNING:\x1b[0m Could not get the transaction ordering mode of the replay node, make sure it mines transactions in FIFO order !!!");
+        }
+    }
+}
+
+// Check that the latest block of the replay node contains the txs we sent in the order we
+// sent them, which is their historical order. `sent` are the hashes the replay node returned
+// for them, so this works for re-signed and unsigned txs too.
+pub async fn is_mined_in_order(
+    replay_rpc: &RpcConnection,
+    sent: &[String],
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mined_block = replay_rpc.get_block_by_number("latest".to_string()).await?;
+    let mined_block: BlockResult = serde_json::from_str(&mined_block)?;
+    let mined: Vec<String> = mined_block
+        .transactions
+        .iter()
+        .map(|tx| tx.hash.clone())
+        .collect();
+
+    Ok(is_in_sent_order(sent, &mined))
+}
+
+// Check that the txs in `mined` that are also in `sent` appear in the same order.
+fn is_in_sent_order(sent: &[String], mined: &[String]) -> bool {
+    let sent: Vec<String> = sent.iter().map(|hash| hash.to_lowercase()).collect();
+    let mined: Vec<String> = mined.iter().map(|hash| hash.to_lowercase()).collect();
+
+    let sent_mined: Vec<&String> = sent.iter().filter(|hash| mined.contains(hash)).collect();
+    let mined_sent: Vec<&String> = mined.iter().filter(|hash| sent.contains(hash)).collect();
+
+    sent_mined == mined_sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(hashes: &[&str]) -> Vec<String> {
+        hashes.iter().map(|hash| hash.to_string()).collect()
+    }
+
+    #[test]
+    fn accepts_the_sent_order() {
+        let sent = hashes(&["0xaa", "0xbb", "0xcc"]);
+        assert!(is_in_sent_order(&sent, &hashes(&["0xaa", "0xbb", "0xcc"])));
+        // Other txs in the block and case don't matter
+        assert!(is_in_sent_order(
+            &sent,
+            &hashes(&["0x11", "0xAA", "0xbb", "0x22", "0xCC"])
+        ));
+        // Neither do txs that didn't get mined
+        assert!(is_in_sent_order(&sent, &hashes(&["0xaa", "0xcc"])));
+        assert!(is_in_sent_order(&[], &hashes(&["0xaa"])));
+    }
+
+    #[test]
+    fn rejects_shuffled_blocks() {
+        let sent = hashes(&["0xaa", "0xbb", "0xcc"]);
+        assert!(!is_in_sent_order(&sent, &hashes(&["0xbb", "0xaa", "0xcc"])));
+        assert!(!is_in_sent_order(&sent, &hashes(&["0x11", "0xcc", "0xaa"])));
+    }
+}
@@ -0,0 +1,61 @@
+use std::thread::sleep;
+use std::time::Instant;
+
+use tokio::time::Duration;
+
+// Decides how long to wait before replaying the next block.
+//
+// By default we sleep `replay_delay` ms between blocks. If a replay speed is set we
+// instead reproduce the historical block times, so a block that came 12s after the
+// first one gets replayed 12s (or 1.2s at 10x) after we replayed the first one.
+pub struct Pacer {
+    replay_delay: u64,
+    replay_speed: Option<f64>,
+    // When we replayed the first block, and its historical timestamp
+    start: Option<(Instant, u64)>,
+}
+
+impl Pacer {
+    pub fn new(
+        replay_delay: u64,
+        replay_speed: Option<f64>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // NaN isn't <= 0 either, and would panic once we wait for a block
+        if replay_speed.is_some_and(|speed| !speed.is_finite() || speed <= 0.0) {
+            return Err("Replay speed must be a number higher than 0".into());
+        }
+
+        Ok(Self {
+            replay_delay,
+            replay_speed,
+            start: None,
+        })
+    }
+
+    // Wait until it's time to replay a block with the historical `timestamp`.
+    pub fn wait(&mut self, timestamp: u64) {
+        let (start_time, start_timestamp) = match self.start {
+            Some(start) => start,
+            None => {
+                self.start = Some((Instant::now(), timestamp));
+                return;
+            }
+        };
+
+        let speed = match self.replay_speed {
+            Some(speed) => speed,
+            None => {
+                sleep(Duration::from_millis(self.replay_delay));
+                return;
+            }
+        };
+
+        let historical_elapsed = timestamp.saturating_sub(start_timestamp) as f64;
+        let target = start_time + Duration::from_secs_f64(historical_elapsed / speed);
+
+        // If we're lagging behind we replay right away and let the node catch up
+        if let Some(wait) = target.checked_duration_since(Instant::now()) {
+            sleep(wait);
+        }
+    }
+}
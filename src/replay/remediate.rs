@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+use ethers::types::U256;
+
+use crate::replay::report::{
+    FailureReason,
+    Patch,
+    PatchKind,
+};
+use crate::rpc::error::RequestError;
+use crate::rpc::format::hex_to_decimal;
+use crate::rpc::types::Transaction;
+use crate::RpcConnection;
+
+// Patch the state of `sender` so `tx` can go through after it failed with `reason`.
+// Returns `None` if the failure isn't something we know how to fix.
+//
+// We only ever touch the sender of the tx, and everything we change is returned so
+// it ends up in the replay report.
+pub async fn remediate(
+    replay_rpc: &RpcConnection,
+    sender: &str,
+    tx: &Transaction,
+    reason: FailureReason,
+) -> Result<Option<Patch>, RequestError> {
+    let (kind, before, after) = match reason {
+        FailureReason::InsufficientFunds => {
+            let balance = replay_rpc
+                .get_balance(sender.to_string(), "latest".to_string())
+                .await?;
+
+            // Top up by the most the tx could cost, so other pending txs of the sender still fit
+            let gas_price = tx.maxFeePerGas.as_ref().unwrap_or(&tx.gasPrice);
+            let parse = |value: &str| {
+                U256::from_str(value).map_err(|e| RequestError::UnknownError(e.into()))
+            };
+            let cost = parse(&tx.gas)?
+                .saturating_mul(parse(gas_price)?)
+                .saturating_add(parse(&tx.value)?);
+            let topped_up = balance.saturating_add(cost);
+
+            replay_rpc
+                .anvil_set_balance(sender.to_string(), topped_up)
+                .await?;
+            (
+                PatchKind::Balance,
+                format!("{:#x}", balance),
+                format!("{:#x}", topped_up),
+            )
+        }
+        FailureReason::NonceTooLow | FailureReason::NonceTooHigh => {
+            let nonce = replay_rpc
+                .get_transaction_count(sender.to_string(), "latest".to_string())
+                .await?;
+
+            replay_rpc
+                .anvil_set_nonce(sender.to_string(), tx.nonce.clone())
+                .await?;
+            (PatchKind::Nonce, nonce, tx.nonce.clone())
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(Patch {
+        block_number: hex_to_decimal(&tx.blockNumber).unwrap_or_default(),
+        hash: tx.hash.clone(),
+        address: sender.to_string(),
+        kind,
+        before,
+        after,
+    }))
+}
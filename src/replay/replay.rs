@@ -1,124 +1,542 @@
-use std::thread::sleep;
-use tokio::time::Duration;
+use std::collections::VecDeque;
+use tokio::time::{
+    Duration,
+    Instant,
+};
 
+use crate::archive::source::BlockSource;
+use crate::replay::divergence::{
+    DivergenceReport,
+    MinedBlock,
+};
+use crate::replay::header::HeaderMirror;
+use crate::replay::ordering::*;
+use crate::replay::pacing::Pacer;
+use crate::replay::report::ReplayReport;
+use crate::replay::resign::Resigner;
+use crate::replay::rewrite::Rewriter;
 use crate::replay::send_transaction::send_transactions;
+use crate::replay::types::*;
+use crate::replay::withdrawals::apply_withdrawals;
 use crate::rpc::format::*;
 use crate::rpc::types::*;
 use crate::RpcConnection;
 
-// To replay historic blocks we:
-// 0) Make sure that the chainids match
-// 1) Set the `evm_autoMine` mode to create blocks
-// 2) Set the `evm_set_interval_mining` to something ridiculously high.
-// 3) Get transaction hashes from block
-// 4) Get transactions from hashes, `eth_sendTransaction` that to the mempool
-// 5) Loop for all transactions in a block
-// 6) Set next block timestamp
-// 7) `evm_mine` the block
-pub async fn replay_historic_blocks(
-    source_rpc: RpcConnection,
+// State we keep around while replaying blocks to a node.
+pub struct Replayer {
     replay_rpc: RpcConnection,
-    until: u64,
-    replay_delay: u64,
-    entropy_threshold: f32,
-    exit_on_tx_fail: bool,
-    send_as_raw: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // make sure that both rpcs have the same chainid to satisfy the replay thingy
-    let historical_chainid = source_rpc.chain_id().await?;
-    let replay_chainid = replay_rpc.chain_id().await?;
+    chain_id: u64,
+    config: ReplayConfig,
+    header_mirror: HeaderMirror,
+    resigner: Option<Resigner>,
+    rewriter: Option<Rewriter>,
+    report: ReplayReport,
+}
+
+impl Replayer {
+    // Make sure we can replay blocks of `historical_chainid` and prepare the replay node.
+    pub async fn new(
+        replay_rpc: RpcConnection,
+        historical_chainid: Option<String>,
+        config: ReplayConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // make sure that both rpcs have the same chainid to satisfy the replay thingy
+        let replay_chainid = replay_rpc.chain_id().await?;
+
+        match historical_chainid {
+            Some(historical_chainid)
+                if historical_chainid != replay_chainid && !config.resign() =>
+            {
+                return Err(
+                    "Chain IDs don't match, use --key_file or --key_seed to re-sign transactions"
+                        .into(),
+                );
+            }
+            Some(_) => (),
+            None => println!("!!! \x1b[93mWARNING:\x1b[0m Block source has no chain ID, assuming it matches the replay node !!!"),
+        }
+
+        // set automine to false
+        replay_rpc.evm_set_automine(false).await?;
+        // set insanely high interval for the blocks
+        replay_rpc.evm_set_interval_mining(u32::MAX.into()).await?;
+
+        if config.preserve_order {
+            check_transaction_order(&replay_rpc).await;
+        }
 
-    if historical_chainid != replay_chainid {
-        return Err("Chain IDs don't match".into());
+        let resigner = match config.resign() {
+            true => {
+                Some(Resigner::new(
+                    config.key_file.clone(),
+                    config.key_seed.clone(),
+                    config.hash_map_file.clone(),
+                )?)
+            }
+            false => None,
+        };
+
+        let rewriter = match &config.rewrite_file {
+            Some(rewrite_file) => Some(Rewriter::new(rewrite_file)?),
+            None => None,
+        };
+
+        Ok(Self {
+            chain_id: hex_to_decimal(&replay_chainid)?,
+            replay_rpc,
+            config,
+            header_mirror: HeaderMirror::default(),
+            resigner,
+            rewriter,
+            report: ReplayReport::default(),
+        })
     }
 
-    // get block mumber of replay node
-    let mut replay_block = hex_to_decimal(&replay_rpc.block_number().await?)?;
-    if replay_block > until {
-        return Err("Replay node block must be less than termination block".into());
+    // Gets the current block number of the replay node.
+    pub async fn block_number(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(hex_to_decimal(&self.replay_rpc.block_number().await?)?)
     }
 
-    // set automine to false
-    replay_rpc.evm_set_automine(false).await?;
-    // set insanely high interval for the blocks
-    replay_rpc
-        .evm_set_interval_mining(std::u32::MAX.into())
-        .await?;
+    // Replay `historical_block` on top of the current head of the replay node.
+    pub async fn replay_block(
+        &mut self,
+        historical_block: &BlockResult,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let block_number = hex_to_decimal(&historical_block.number)?;
 
-    loop {
-        // we write a bit of illegible code
-        let hex_block = decimal_to_hex(replay_block + 1);
-        // get block from historical node
-        let historical_block = source_rpc.get_block_by_number(hex_block.clone()).await?;
+        let sent = self.mine_historical_block(historical_block).await?;
+
+        // Re-mining the same txs on the same state would shuffle them the same way, so the
+        // order is up to the node, e.g. anvil started with `--order fifo`
+        if self.config.preserve_order {
+            let in_order = is_mined_in_order(&self.replay_rpc, &sent).await?;
+            if !in_order {
+                println!(
+                    "!!! \x1b[93mWARNING:\x1b[0m Block {} was not mined in the historical order !!!",
+                    block_number
+                );
+            }
+            self.report.record_order(block_number, in_order);
+        }
 
-        // get transaction hashes from block
-        let historical_block: BlockResult = serde_json::from_str(&historical_block)?;
-        let historical_txs = historical_block.transactions;
+        // apply withdrawals after the block's transactions, same as mainnet
+        if let Some(withdrawals) = &historical_block.withdrawals {
+            apply_withdrawals(&self.replay_rpc, withdrawals).await?;
+        }
+
+        Ok(())
+    }
+
+    // Send the txs of `historical_block` to the replay node and mine them with the historical header.
+    // Returns the hashes of the txs we sent, in the order we sent them.
+    async fn mine_historical_block(
+        &mut self,
+        historical_block: &BlockResult,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let block_number = hex_to_decimal(&historical_block.number)?;
+
+        // set coinbase, base fee, gas limit and prevrandao before sending
+        // so the txs get validated against the historical header
+        self.header_mirror
+            .set_next_block_header(&self.replay_rpc, historical_block)
+            .await;
+
+        // swap contract code scheduled for this block before its txs run
+        let code_swaps = match &self.rewriter {
+            Some(rewriter) => rewriter.swap_code(&self.replay_rpc, block_number).await?,
+            None => Vec::new(),
+        };
 
         // send transactions to mempool
-        send_transactions(
-            replay_rpc.clone(),
-            historical_txs,
-            hex_to_decimal(&replay_chainid)?,
-            entropy_threshold,
-            exit_on_tx_fail,
-            send_as_raw,
+        let mut outcome = send_transactions(
+            self.replay_rpc.clone(),
+            historical_block.transactions.clone(),
+            self.chain_id,
+            &self.config,
+            &mut self.resigner,
+            &self.rewriter,
         )
         .await?;
+        outcome.rewrites.splice(0..0, code_swaps);
+        let sent = std::mem::take(&mut outcome.hashes);
+        self.report
+            .record_block(block_number, historical_block.transactions.len(), outcome);
 
         // set next block timestamp
-        replay_rpc
+        self.replay_rpc
             .evm_set_next_block_timestamp(hex_to_decimal(&historical_block.timestamp)?)
             .await?;
 
         // mine the block
-        replay_rpc.evm_mine().await?;
-        println!(
-            "Successfully replayed block {}",
-            hex_to_decimal(&hex_block)?
-        );
+        self.replay_rpc.evm_mine().await?;
+
+        Ok(sent)
+    }
+
+    // Write the report of the replayed blocks to the report file if we have one.
+    pub fn write_report(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(report_file) = &self.config.report_file {
+            self.report.write(report_file)?;
+        }
+
+        Ok(())
+    }
+
+    // Print anything we have to report about the replayed blocks, and write it to
+    // the report file if we have one.
+    pub fn finish(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.report.print(self.config.preserve_order);
+        self.write_report()
+    }
+}
+
+// Drives one `Replayer` per replay node in lockstep, so every node gets the same
+// blocks from a single fetched block stream. With more than one node we compare
+// what they mined after every block.
+pub struct ReplayNodes {
+    replayers: Vec<Replayer>,
+    divergence_report: DivergenceReport,
+    divergence_file: Option<String>,
+}
+
+impl ReplayNodes {
+    pub async fn new(
+        replay_rpcs: Vec<RpcConnection>,
+        historical_chainid: Option<String>,
+        config: ReplayConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let node_count = replay_rpcs.len();
+        let mut replayers = Vec::new();
 
-        replay_block = hex_to_decimal(&replay_rpc.block_number().await?)?;
+        for (index, replay_rpc) in replay_rpcs.into_iter().enumerate() {
+            let mut node_config = config.clone();
+            // Every node gets its own report and hash map, `report.json` becomes
+            // `report-0.json`, ...
+            if node_count > 1 {
+                node_config.report_file = config
+                    .report_file
+                    .as_ref()
+                    .map(|report_file| node_report_file(report_file, index));
+                node_config.hash_map_file = node_report_file(&config.hash_map_file, index);
+            }
+
+            replayers
+                .push(Replayer::new(replay_rpc, historical_chainid.clone(), node_config).await?);
+        }
+
+        Ok(Self {
+            replayers,
+            divergence_report: DivergenceReport::default(),
+            divergence_file: config.divergence_file,
+        })
+    }
+
+    // Gets the block number all replay nodes are at, they have to start from the same block.
+    pub async fn block_number(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        let block_number = self.replayers[0].block_number().await?;
+
+        for replayer in &self.replayers[1..] {
+            if replayer.block_number().await? != block_number {
+                return Err("Replay nodes are not at the same block".into());
+            }
+        }
+
+        Ok(block_number)
+    }
+
+    pub async fn replay_block(
+        &mut self,
+        historical_block: &BlockResult,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let block_number = hex_to_decimal(&historical_block.number)?;
+
+        for replayer in &mut self.replayers {
+            replayer.replay_block(historical_block).await?;
+        }
+
+        if self.replayers.len() > 1 {
+            let mut mined = Vec::new();
+            for replayer in &self.replayers {
+                mined.push(MinedBlock::latest(&replayer.replay_rpc).await?);
+            }
+            self.divergence_report.check(block_number, mined);
+        }
+
+        println!("Successfully replayed block {}", block_number);
+
+        Ok(())
+    }
+
+    // Snapshot every replay node, returns the snapshot ids in node order.
+    pub async fn snapshot(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut snapshot_ids = Vec::new();
+        for replayer in &self.replayers {
+            snapshot_ids.push(replayer.replay_rpc.evm_snapshot().await?);
+        }
+
+        Ok(snapshot_ids)
+    }
+
+    // Revert every replay node to the snapshot taken before `block_number`.
+    pub async fn revert(
+        &mut self,
+        block_number: u64,
+        snapshot_ids: &[String],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for (replayer, snapshot_id) in self.replayers.iter_mut().zip(snapshot_ids) {
+            replayer.replay_rpc.evm_revert(snapshot_id.clone()).await?;
+            if let Some(resigner) = &mut replayer.resigner {
+                resigner.revert(block_number);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn write_reports(&self) -> Result<(), Box<dyn std::error::Error>> {
+        for replayer in &self.replayers {
+            replayer.write_report()?;
+        }
+        if let Some(divergence_file) = &self.divergence_file {
+            self.divergence_report.write(divergence_file)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.replayers.len() == 1 {
+            return self.replayers[0].finish();
+        }
+
+        for replayer in &self.replayers {
+            println!("Results for {}:", replayer.replay_rpc.url());
+            replayer.finish()?;
+        }
+        self.divergence_report.print();
+        if let Some(divergence_file) = &self.divergence_file {
+            self.divergence_report.write(divergence_file)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Insert the index of a replay node before the extension of `path`.
+fn node_report_file(path: &str, index: usize) -> String {
+    match path.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}-{}.{}", stem, index, extension),
+        None => format!("{}-{}", path, index),
+    }
+}
+
+// To replay historic blocks we:
+// 0) Make sure that the chainids match, or that we're re-signing txs for the replay chain
+// 1) Set the `evm_autoMine` mode to create blocks
+// 2) Set the `evm_set_interval_mining` to something ridiculously high.
+// 3) Get transaction hashes from block
+// 4) Get transactions from hashes, `eth_sendTransaction` that to the mempool
+// 5) Loop for all transactions in a block
+// 6) Set next block timestamp and mirror the rest of the header
+// 7) `evm_mine` the block
+// 8) If we're preserving the order, check that the mined block kept it
+// 9) Credit the block's beacon chain withdrawals
+pub async fn replay_historic_blocks(
+    source: &mut dyn BlockSource,
+    replay_rpcs: Vec<RpcConnection>,
+    until: u64,
+    config: ReplayConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pacer = Pacer::new(config.replay_delay, config.replay_speed)?;
+    let historical_chainid = source.get_chain_id().await?;
+    let mut nodes = ReplayNodes::new(replay_rpcs, historical_chainid, config).await?;
+
+    // get block mumber of replay nodes
+    let mut replay_block = nodes.block_number().await?;
+    if replay_block > until {
+        return Err("Replay node block must be less than termination block".into());
+    }
+
+    loop {
+        // get block from historical node or file
+        let historical_block = source.get_block(replay_block + 1).await?;
+
+        pacer.wait(hex_to_decimal(&historical_block.timestamp)?);
+        nodes.replay_block(&historical_block).await?;
+
+        replay_block = nodes.block_number().await?;
 
         if replay_block >= until {
             break;
         }
-
-        sleep(Duration::from_millis(replay_delay));
     }
+
+    nodes.finish()?;
     println!("Done replaying blocks");
     Ok(())
 }
 
 // To replay live blocks we:
 // 0) Assume that we are lagging behind the head.
-// 1) Catch up to the head block by using `replay_historic_blocks`.
+// 1) Catch up to the head block, snapshotting the replay node every `MAX_REORG_DEPTH` blocks.
 // 2) Once we caught up, listen for new blocks.
-// 3) Repeat from 2.
-#[allow(dead_code, unused_variables)]
+// 3) If the source reorged blocks we replayed, revert to the last snapshot before the
+//    first orphaned block, and replay the canonical blocks from there.
+// 4) Repeat from 1.
 pub async fn replay_live(
-    source_rpc: RpcConnection,
-    replay_rpc: RpcConnection,
-    replay_delay: u64,
+    mut source_rpc: RpcConnection,
+    replay_rpcs: Vec<RpcConnection>,
     block_listen_time: u64,
-    entropy_threshold: f32,
-    exit_on_tx_fail: bool,
-    send_as_raw: bool,
+    config: ReplayConfig,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pacer = Pacer::new(config.replay_delay, config.replay_speed)?;
+    let historical_chainid = source_rpc.chain_id().await?;
+    let mut nodes = ReplayNodes::new(replay_rpcs, Some(historical_chainid), config).await?;
+
+    // Blocks we replayed that could still get reorged, oldest first
+    let mut replayed: VecDeque<ReplayedBlock> = VecDeque::new();
+    // Blocks in a row that didn't extend the replayed chain
+    let mut mismatches = 0;
+    let mut last_report = Instant::now();
+
     loop {
-        let latest_block = source_rpc.listen_for_blocks(block_listen_time).await?;
-        if latest_block != replay_rpc.block_number().await? {
+        let latest_block = hex_to_decimal(&source_rpc.listen_for_blocks(block_listen_time).await?)?;
+
+        handle_reorg(&source_rpc, &mut nodes, &mut replayed).await?;
+
+        let mut replay_block = nodes.block_number().await?;
+        let behind = replay_block < latest_block;
+        if behind {
             println!("New block detected, replaying...");
-            replay_historic_blocks(
-                source_rpc.clone(),
-                replay_rpc.clone(),
-                hex_to_decimal(&latest_block)?,
-                replay_delay,
-                entropy_threshold,
-                exit_on_tx_fail,
-                send_as_raw,
+        }
+
+        while replay_block < latest_block {
+            let historical_block = source_rpc.get_block(replay_block + 1).await?;
+
+            // The source reorged between checking and fetching the block
+            if replayed
+                .back()
+                .map(|parent| parent.hash != historical_block.parentHash)
+                .unwrap_or(false)
+            {
+                mismatches += 1;
+                if mismatches > MAX_REORG_RETRIES {
+                    return Err(format!(
+                        "Block {} of the source still doesn't extend the replayed chain after {} retries",
+                        replay_block + 1,
+                        MAX_REORG_RETRIES
+                    )
+                    .into());
+                }
+
+                handle_reorg(&source_rpc, &mut nodes, &mut replayed).await?;
+                // The source might still be switching branches, give it some time
+                tokio::time::sleep(Duration::from_secs(1 << mismatches)).await;
+                replay_block = nodes.block_number().await?;
+                continue;
+            }
+            mismatches = 0;
+
+            pacer.wait(hex_to_decimal(&historical_block.timestamp)?);
+
+            let since_snapshot = replayed
+                .iter()
+                .rev()
+                .position(|block| block.snapshot_ids.is_some());
+            let snapshot_ids = match since_snapshot {
+                Some(since_snapshot) if since_snapshot + 1 < MAX_REORG_DEPTH => None,
+                _ => Some(nodes.snapshot().await?),
+            };
+            nodes.replay_block(&historical_block).await?;
+
+            replayed.push_back(ReplayedBlock {
+                number: replay_block + 1,
+                hash: historical_block.hash.clone(),
+                parent_hash: historical_block.parentHash.clone(),
+                snapshot_ids,
+            });
+            // Keeping twice the depth leaves a snapshot before any of the last `MAX_REORG_DEPTH` blocks
+            if replayed.len() > 2 * MAX_REORG_DEPTH {
+                replayed.pop_front();
+            }
+
+            replay_block = nodes.block_number().await?;
+        }
+
+        // Live replay never ends, so keep the report files up to date
+        if behind && last_report.elapsed() >= REPORT_INTERVAL {
+            nodes.write_reports()?;
+            last_report = Instant::now();
+        }
+    }
+}
+
+// Compare the blocks we replayed against the canonical chain of the source,
+// and roll the replay node back to the common ancestor if they got reorged.
+async fn handle_reorg(
+    source_rpc: &RpcConnection,
+    nodes: &mut ReplayNodes,
+    replayed: &mut VecDeque<ReplayedBlock>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut depth = 0;
+
+    // Walk back from our tip until we find a block that is still canonical
+    // The source might not have a block at this height anymore if it reorged to a shorter chain
+    for block in replayed.iter().rev() {
+        let canonical: Option<BlockResult> = serde_json::from_str(
+            &source_rpc
+                .get_block_by_number(decimal_to_hex(block.number))
+                .await?,
+        )?;
+        if canonical
+            .map(|canonical| canonical.hash == block.hash)
+            .unwrap_or(false)
+        {
+            break;
+        }
+        depth += 1;
+    }
+
+    if depth == 0 {
+        return Ok(());
+    }
+
+    // Every block we know of got orphaned, check that the parent of the oldest one is still canonical
+    if depth == replayed.len() {
+        let oldest = &replayed[0];
+        let canonical: BlockResult = serde_json::from_str(
+            &source_rpc
+                .get_block_by_number(decimal_to_hex(oldest.number - 1))
+                .await?,
+        )?;
+        if canonical.hash != oldest.parent_hash {
+            return Err(format!(
+                "Reorg is deeper than the last {} replayed blocks, cannot roll back",
+                replayed.len()
             )
-            .await?;
+            .into());
         }
     }
+
+    // Revert to the last snapshot before the first orphaned block, the blocks between it and
+    // the common ancestor are still canonical and get replayed again
+    let first_orphaned = replayed.len() - depth;
+    let snapshot = replayed
+        .range(..=first_orphaned)
+        .rposition(|block| block.snapshot_ids.is_some())
+        .ok_or("No snapshot before the reorged blocks, cannot roll back")?;
+    let reverted = replayed.split_off(snapshot);
+    let snapshot_ids = reverted[0]
+        .snapshot_ids
+        .as_ref()
+        .expect("Block has a snapshot");
+    nodes.revert(reverted[0].number, snapshot_ids).await?;
+
+    println!(
+        "!!! \x1b[93mReorg of depth {} detected!\x1b[0m Rolled back to block {} !!!",
+        depth,
+        reverted[0].number - 1
+    );
+
+    Ok(())
 }
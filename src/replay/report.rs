@@ -0,0 +1,457 @@
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+use std::fs;
+
+use serde::Serialize;
+
+use crate::rpc::format::hex_to_decimal;
+use crate::rpc::types::Transaction;
+
+// Why a transaction could not be replayed, classified from the error message of the node.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureReason {
+    NonceTooLow,
+    NonceTooHigh,
+    InsufficientFunds,
+    IntrinsicGas,
+    Underpriced,
+    InvalidSignature,
+    Unknown,
+}
+
+impl FailureReason {
+    // Anvil, hardhat and geth all word these a bit differently, so we match loosely
+    pub fn classify(message: &str) -> Self {
+        let message = message.to_lowercase();
+
+        if message.contains("nonce too low") || message.contains("nonce is too low") {
+            FailureReason::NonceTooLow
+        } else if message.contains("nonce too high") || message.contains("nonce is too high") {
+            FailureReason::NonceTooHigh
+        } else if message.contains("insufficient funds") || message.contains("insufficient balance")
+        {
+            FailureReason::InsufficientFunds
+        } else if message.contains("intrinsic gas") {
+            FailureReason::IntrinsicGas
+        } else if message.contains("underpriced")
+            || message.contains("less than block base fee")
+            || message.contains("fee cap")
+        {
+            FailureReason::Underpriced
+        } else if message.contains("signature") || message.contains("invalid sender") {
+            FailureReason::InvalidSignature
+        } else {
+            FailureReason::Unknown
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureReason::NonceTooLow => "nonce_too_low",
+            FailureReason::NonceTooHigh => "nonce_too_high",
+            FailureReason::InsufficientFunds => "insufficient_funds",
+            FailureReason::IntrinsicGas => "intrinsic_gas",
+            FailureReason::Underpriced => "underpriced",
+            FailureReason::InvalidSignature => "invalid_signature",
+            FailureReason::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedTransaction {
+    pub block_number: u64,
+    pub hash: String,
+    pub from: String,
+    pub nonce: String,
+    pub tx_type: String,
+    pub reason: FailureReason,
+    pub message: String,
+}
+
+impl FailedTransaction {
+    pub fn new(tx: &Transaction, message: String) -> Self {
+        Self {
+            block_number: hex_to_decimal(&tx.blockNumber).unwrap_or_default(),
+            hash: tx.hash.clone(),
+            from: tx.from.clone(),
+            nonce: tx.nonce.clone(),
+            tx_type: tx.txType.clone(),
+            reason: FailureReason::classify(&message),
+            message,
+        }
+    }
+
+    pub fn serialize_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},\"{}\"",
+            self.block_number,
+            self.hash,
+            self.from,
+            self.nonce,
+            self.tx_type,
+            self.reason.as_str(),
+            self.message.replace('"', "'")
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchKind {
+    Balance,
+    Nonce,
+}
+
+// State we injected into the replay node to get a failed tx through.
+#[derive(Debug, Clone, Serialize)]
+pub struct Patch {
+    pub block_number: u64,
+    // Tx we patched the state for
+    pub hash: String,
+    pub address: String,
+    pub kind: PatchKind,
+    pub before: String,
+    pub after: String,
+}
+
+impl PatchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PatchKind::Balance => "balance",
+            PatchKind::Nonce => "nonce",
+        }
+    }
+}
+
+impl Patch {
+    pub fn serialize_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.block_number,
+            self.hash,
+            self.address,
+            self.kind.as_str(),
+            self.before,
+            self.after
+        )
+    }
+}
+
+// A what-if modification we applied while replaying.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rewrite {
+    pub block_number: u64,
+    // Tx we rewrote, `None` for code swaps
+    pub hash: Option<String>,
+    pub changes: Vec<String>,
+}
+
+impl Rewrite {
+    pub fn serialize_csv(&self) -> String {
+        format!(
+            "{},{},\"{}\"",
+            self.block_number,
+            self.hash.as_deref().unwrap_or_default(),
+            self.changes.join("; ")
+        )
+    }
+}
+
+// Everything that happened while sending the txs of a block.
+#[derive(Debug, Default)]
+pub struct BlockOutcome {
+    // Hashes the replay node returned for the txs we sent, in the order we sent them
+    pub hashes: Vec<String>,
+    pub failures: Vec<FailedTransaction>,
+    pub patches: Vec<Patch>,
+    pub rewrites: Vec<Rewrite>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockStats {
+    pub block_number: u64,
+    pub sent: usize,
+    pub failed: usize,
+    pub patched: usize,
+    // Ratio of failed txs in the block
+    pub entropy: f32,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AggregateStats {
+    pub blocks: usize,
+    pub sent: usize,
+    pub failed: usize,
+    pub patched: usize,
+    pub entropy: f32,
+    pub max_block_entropy: f32,
+}
+
+impl AggregateStats {
+    fn add(&mut self, block: &BlockStats) {
+        self.blocks += 1;
+        self.sent += block.sent;
+        self.failed += block.failed;
+        self.patched += block.patched;
+        self.max_block_entropy = self.max_block_entropy.max(block.entropy);
+        self.update_entropy();
+    }
+
+    fn remove(&mut self, block: &BlockStats) {
+        self.blocks -= 1;
+        self.sent -= block.sent;
+        self.failed -= block.failed;
+        self.patched -= block.patched;
+        self.update_entropy();
+    }
+
+    fn update_entropy(&mut self) {
+        self.entropy = match self.sent {
+            0 => 0.0,
+            _ => self.failed as f32 / self.sent as f32,
+        };
+    }
+}
+
+// Everything recorded for one replayed block.
+#[derive(Debug)]
+struct BlockReport {
+    stats: BlockStats,
+    failures: Vec<FailedTransaction>,
+    patches: Vec<Patch>,
+    rewrites: Vec<Rewrite>,
+}
+
+// Everything that went wrong while replaying, written to a file at the end of the run.
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    aggregate: AggregateStats,
+    blocks: BTreeMap<u64, BlockReport>,
+    // Blocks where we could not reproduce the historical transaction order
+    unordered_blocks: BTreeSet<u64>,
+}
+
+// What gets written to the report file.
+#[derive(Serialize)]
+struct ReportFile<'a> {
+    aggregate: &'a AggregateStats,
+    blocks: Vec<&'a BlockStats>,
+    failures: Vec<&'a FailedTransaction>,
+    patches: Vec<&'a Patch>,
+    rewrites: Vec<&'a Rewrite>,
+    unordered_blocks: &'a BTreeSet<u64>,
+}
+
+impl ReplayReport {
+    // Record the result of sending the txs of a block. If the block was already
+    // recorded (reorged) we replace the previous result.
+    pub fn record_block(&mut self, block_number: u64, sent: usize, outcome: BlockOutcome) {
+        let BlockOutcome {
+            hashes: _,
+            failures,
+            patches,
+            rewrites,
+        } = outcome;
+
+        let entropy = match sent {
+            0 => 0.0,
+            _ => failures.len() as f32 / sent as f32,
+        };
+        let stats = BlockStats {
+            block_number,
+            sent,
+            failed: failures.len(),
+            patched: patches.len(),
+            entropy,
+        };
+        self.aggregate.add(&stats);
+
+        let replaced = self.blocks.insert(
+            block_number,
+            BlockReport {
+                stats,
+                failures,
+                patches,
+                rewrites,
+            },
+        );
+        if let Some(replaced) = replaced {
+            self.aggregate.remove(&replaced.stats);
+            // Only reorgs replace blocks, so this rarely has to look at all of them
+            if replaced.stats.entropy >= self.aggregate.max_block_entropy {
+                self.aggregate.max_block_entropy = self
+                    .blocks
+                    .values()
+                    .map(|block| block.stats.entropy)
+                    .fold(0.0, f32::max);
+            }
+        }
+    }
+
+    // Record whether a block was mined in the historical order. A block that's replayed
+    // again after a reorg replaces what was recorded for it.
+    pub fn record_order(&mut self, block_number: u64, in_order: bool) {
+        match in_order {
+            true => self.unordered_blocks.remove(&block_number),
+            false => self.unordered_blocks.insert(block_number),
+        };
+    }
+
+    fn failures(&self) -> impl Iterator<Item = &FailedTransaction> {
+        self.blocks.values().flat_map(|block| &block.failures)
+    }
+
+    fn patches(&self) -> impl Iterator<Item = &Patch> {
+        self.blocks.values().flat_map(|block| &block.patches)
+    }
+
+    fn rewrites(&self) -> impl Iterator<Item = &Rewrite> {
+        self.blocks.values().flat_map(|block| &block.rewrites)
+    }
+
+    pub fn print(&self, preserve_order: bool) {
+        println!(
+            "Replayed {} blocks, {} of {} transactions failed. Fail ratio: {:.2}%",
+            self.aggregate.blocks,
+            self.aggregate.failed,
+            self.aggregate.sent,
+            self.aggregate.entropy * 100.0
+        );
+
+        if self.aggregate.patched > 0 {
+            println!(
+                "!!! \x1b[93mWARNING:\x1b[0m Injected {} balance or nonce patches into the replay node, see the report for details !!!",
+                self.aggregate.patched
+            );
+        }
+
+        let rewrites = self.rewrites().count();
+        if rewrites > 0 {
+            println!("Applied {} rewrites, see the report for details", rewrites);
+        }
+
+        if !preserve_order {
+            return;
+        }
+        if self.unordered_blocks.is_empty() {
+            println!("Historical transaction order was preserved in all replayed blocks");
+        } else {
+            println!(
+                "!!! \x1b[91mCould not preserve the historical transaction order in {} block(s):\x1b[0m {:?} !!!",
+                self.unordered_blocks.len(),
+                self.unordered_blocks.iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    // Write the report as JSON, or as CSV if `path` ends with `.csv`. As CSV only fits
+    // one table, the per block stats, patches and rewrites get written next to it,
+    // `report.csv` gets `report-blocks.csv`, `report-patches.csv` and `report-rewrites.csv`.
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        println!("Writing replay report to: {}", path);
+
+        match path.strip_suffix(".csv") {
+            Some(stem) => {
+                let mut failures =
+                    String::from("block_number,hash,from,nonce,tx_type,reason,message\n");
+                for failure in self.failures() {
+                    failures.push_str(&failure.serialize_csv());
+                    failures.push('\n');
+                }
+                fs::write(path, failures)?;
+
+                let mut blocks = String::from("block_number,sent,failed,patched,entropy\n");
+                for block in self.blocks.values().map(|block| &block.stats) {
+                    blocks.push_str(&format!(
+                        "{},{},{},{},{}\n",
+                        block.block_number, block.sent, block.failed, block.patched, block.entropy
+                    ));
+                }
+                fs::write(format!("{}-blocks.csv", stem), blocks)?;
+
+                let mut patches = String::from("block_number,hash,address,kind,before,after\n");
+                for patch in self.patches() {
+                    patches.push_str(&patch.serialize_csv());
+                    patches.push('\n');
+                }
+                fs::write(format!("{}-patches.csv", stem), patches)?;
+
+                let mut rewrites = String::from("block_number,hash,changes\n");
+                for rewrite in self.rewrites() {
+                    rewrites.push_str(&rewrite.serialize_csv());
+                    rewrites.push('\n');
+                }
+                fs::write(format!("{}-rewrites.csv", stem), rewrites)?;
+            }
+            None => {
+                let report = ReportFile {
+                    aggregate: &self.aggregate,
+                    blocks: self.blocks.values().map(|block| &block.stats).collect(),
+                    failures: self.failures().collect(),
+                    patches: self.patches().collect(),
+                    rewrites: self.rewrites().collect(),
+                    unordered_blocks: &self.unordered_blocks,
+                };
+                fs::write(path, serde_json::to_string(&report)?)?
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_node_errors() {
+        let cases = [
+            ("nonce too low", FailureReason::NonceTooLow),
+            (
+                "Nonce is too low. Expected 5, got 3",
+                FailureReason::NonceTooLow,
+            ),
+            ("nonce too high", FailureReason::NonceTooHigh),
+            (
+                "Insufficient funds for gas * price + value",
+                FailureReason::InsufficientFunds,
+            ),
+            (
+                "sender has insufficient balance",
+                FailureReason::InsufficientFunds,
+            ),
+            ("intrinsic gas too low", FailureReason::IntrinsicGas),
+            (
+                "replacement transaction underpriced",
+                FailureReason::Underpriced,
+            ),
+            (
+                "max fee per gas less than block base fee",
+                FailureReason::Underpriced,
+            ),
+            ("invalid signature", FailureReason::InvalidSignature),
+            ("execution reverted", FailureReason::Unknown),
+        ];
+
+        for (message, reason) in cases {
+            assert_eq!(FailureReason::classify(message), reason, "{}", message);
+        }
+    }
+
+    #[test]
+    fn replayed_blocks_replace_their_order() {
+        let mut report = ReplayReport::default();
+        report.record_order(5, false);
+        report.record_order(5, false);
+        // Back in order after a reorg
+        report.record_order(6, false);
+        report.record_order(6, true);
+
+        assert_eq!(report.unordered_blocks, BTreeSet::from([5]));
+    }
+}
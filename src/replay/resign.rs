@@ -0,0 +1,135 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::{
+    self,
+    File,
+    OpenOptions,
+};
+use std::io::Write;
+use std::str::FromStr;
+
+use ethers::signers::{
+    LocalWallet,
+    Signer,
+};
+use ethers::types::H160;
+use ethers::utils::keccak256;
+
+use crate::rpc::error::RequestError;
+use crate::rpc::format::hex_to_decimal;
+use crate::rpc::types::Transaction;
+use crate::RpcConnection;
+
+// Re-signs historical transactions with local keys so they can be replayed on a
+// node with a different chain id.
+//
+// Senders are mapped to keys from a JSON key file (`{"0xsender": "0xprivate_key"}`),
+// or to keys derived from `keccak256(seed ++ sender)` if they aren't in the file.
+// The first time we use a key we copy the balance of the original sender to it
+// and set its nonce to the one of the tx, so the txs of each sender keep lining up.
+// Reverting the replay node undoes that, so keys are set up again after a revert.
+pub struct Resigner {
+    keys: HashMap<String, LocalWallet>,
+    seed: Option<String>,
+    // Keys we funded, and the block we funded them in
+    prepared: HashMap<String, u64>,
+    hash_map: File,
+}
+
+impl Resigner {
+    pub fn new(
+        key_file: Option<String>,
+        seed: Option<String>,
+        hash_map_file: String,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut keys = HashMap::new();
+        if let Some(key_file) = key_file {
+            let file: HashMap<String, String> =
+                serde_json::from_str(&fs::read_to_string(key_file)?)?;
+            for (sender, key) in file {
+                keys.insert(sender.to_lowercase(), LocalWallet::from_str(&key)?);
+            }
+        }
+
+        // We append so live replay doesn't overwrite the mappings of previous runs
+        let hash_map = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&hash_map_file)?;
+        println!(
+            "Writing original to replayed tx hash mappings to: {}",
+            hash_map_file
+        );
+
+        Ok(Self {
+            keys,
+            seed,
+            prepared: HashMap::new(),
+            hash_map,
+        })
+    }
+
+    // Get the key we're signing the txs of `sender` with.
+    fn wallet_for(&mut self, sender: &str) -> Result<LocalWallet, Box<dyn std::error::Error>> {
+        let sender = sender.to_lowercase();
+        if let Some(wallet) = self.keys.get(&sender) {
+            return Ok(wallet.clone());
+        }
+
+        let seed = match &self.seed {
+            Some(seed) => seed,
+            None => return Err(format!("No key found for sender {}", sender).into()),
+        };
+
+        let preimage = [seed.as_bytes(), H160::from_str(&sender)?.as_bytes()].concat();
+        let wallet = LocalWallet::from_bytes(&keccak256(preimage))?;
+        self.keys.insert(sender, wallet.clone());
+
+        Ok(wallet)
+    }
+
+    // Address the txs of `sender` get sent from after re-signing.
+    pub fn signer_of(&mut self, sender: &str) -> Result<String, Box<dyn std::error::Error>> {
+        Ok(format!("{:?}", self.wallet_for(sender)?.address()))
+    }
+
+    // The replay node got reverted to before `block_number`, which undid the funding of the
+    // keys we prepared since, so they have to be funded again.
+    pub fn revert(&mut self, block_number: u64) {
+        self.prepared.retain(|_, funded| *funded < block_number);
+    }
+
+    // Re-sign `tx` for `chain_id` and send it, returns the hash of the replayed tx.
+    pub async fn send(
+        &mut self,
+        replay_rpc: &RpcConnection,
+        tx: &Transaction,
+        chain_id: u64,
+    ) -> Result<String, RequestError> {
+        let wallet = self.wallet_for(&tx.from)?;
+        let address = format!("{:?}", wallet.address());
+
+        if let Entry::Vacant(prepared) = self.prepared.entry(address.clone()) {
+            let balance = replay_rpc
+                .get_balance(tx.from.clone(), "latest".to_string())
+                .await?;
+            replay_rpc
+                .anvil_set_balance(address.clone(), balance)
+                .await?;
+            replay_rpc
+                .anvil_set_nonce(address.clone(), tx.nonce.clone())
+                .await?;
+            // Pending txs of the mempool mode aren't in a block yet
+            let block_number = hex_to_decimal(&tx.blockNumber).unwrap_or_default();
+            prepared.insert(block_number);
+        }
+
+        let rlp = tx.rlp_resign_tx(&wallet, chain_id)?;
+        let hash = replay_rpc.send_raw_transaction_rlp(rlp).await?;
+
+        writeln!(self.hash_map, "{},{}", tx.hash, hash)
+            .map_err(|e| RequestError::UnknownError(e.into()))?;
+
+        Ok(hash)
+    }
+}
@@ -0,0 +1,144 @@
+use std::fs;
+
+use crate::replay::report::Rewrite;
+use crate::replay::types::*;
+use crate::rpc::error::RequestError;
+use crate::rpc::format::hex_to_decimal;
+use crate::rpc::types::Transaction;
+use crate::RpcConnection;
+
+// Applies the what-if modifications of a rewrite file to the txs we replay.
+//
+// Rewritten txs can't keep their original signature, so they get sent from the
+// impersonated sender instead.
+pub struct Rewriter {
+    rules: Vec<RewriteRule>,
+    code: Vec<CodeSwap>,
+}
+
+impl Rewriter {
+    pub fn new(rewrite_file: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let file: RewriteFile = serde_json::from_str(&fs::read_to_string(rewrite_file)?)?;
+        println!(
+            "Loaded {} rewrite rules and {} code swaps from: {}",
+            file.rules.len(),
+            file.code.len(),
+            rewrite_file
+        );
+
+        Ok(Self {
+            rules: file.rules,
+            code: file.code,
+        })
+    }
+
+    // Apply every rule matching `tx` in order, returns `None` if no rule matched.
+    pub fn rewrite(
+        &self,
+        tx: &Transaction,
+    ) -> Result<Option<(Transaction, Rewrite)>, Box<dyn std::error::Error>> {
+        let mut rewritten = tx.clone();
+        let mut changes = Vec::new();
+
+        for rule in &self.rules {
+            if !matches(&rule.matcher, &rewritten) {
+                continue;
+            }
+            let replace = &rule.replace;
+
+            if let Some(to) = &replace.to {
+                changes.push(format!(
+                    "to: {} -> {}",
+                    rewritten.to.as_deref().unwrap_or("none"),
+                    to
+                ));
+                rewritten.to = Some(to.clone());
+            }
+            if let Some(value) = &replace.value {
+                changes.push(format!("value: {} -> {}", rewritten.value, value));
+                rewritten.value = value.clone();
+            }
+            if let Some(gas) = &replace.gas {
+                changes.push(format!("gas: {} -> {}", rewritten.gas, gas));
+                rewritten.gas = gas.clone();
+            }
+            if let Some(input) = &replace.input {
+                changes.push("input: replaced".to_string());
+                rewritten.input = input.clone();
+            }
+            for arg in &replace.args {
+                if arg.value.trim_start_matches("0x").len() > 64 {
+                    return Err(format!("Argument {} is longer than 32 bytes", arg.value).into());
+                }
+                let before = replace_arg(&mut rewritten.input, arg)
+                    .ok_or(format!("Tx {} has no argument {}", tx.hash, arg.index))?;
+                changes.push(format!("arg {}: {} -> {}", arg.index, before, arg.value));
+            }
+        }
+
+        if changes.is_empty() {
+            return Ok(None);
+        }
+
+        let rewrite = Rewrite {
+            block_number: hex_to_decimal(&tx.blockNumber).unwrap_or_default(),
+            hash: Some(tx.hash.clone()),
+            changes,
+        };
+        Ok(Some((rewritten, rewrite)))
+    }
+
+    // Swap the code of every contract scheduled for `block_number`.
+    pub async fn swap_code(
+        &self,
+        replay_rpc: &RpcConnection,
+        block_number: u64,
+    ) -> Result<Vec<Rewrite>, RequestError> {
+        let mut rewrites = Vec::new();
+
+        for swap in self.code.iter().filter(|swap| swap.block == block_number) {
+            replay_rpc
+                .anvil_set_code(swap.address.clone(), swap.code.clone())
+                .await?;
+            println!(
+                "Swapped the code of {} at block {}",
+                swap.address, block_number
+            );
+
+            rewrites.push(Rewrite {
+                block_number,
+                hash: None,
+                changes: vec![format!("code of {}: replaced", swap.address)],
+            });
+        }
+
+        Ok(rewrites)
+    }
+}
+
+fn matches(matcher: &TxMatcher, tx: &Transaction) -> bool {
+    let eq = |expected: &Option<String>, actual: &str| {
+        expected
+            .as_ref()
+            .map(|expected| expected.eq_ignore_ascii_case(actual))
+            .unwrap_or(true)
+    };
+
+    eq(&matcher.hash, &tx.hash)
+        && eq(&matcher.from, &tx.from)
+        && eq(&matcher.to, tx.to.as_deref().unwrap_or_default())
+        && eq(&matcher.selector, tx.input.get(..10).unwrap_or_default())
+}
+
+// Replace an ABI encoded argument in `input`, returns the previous value or `None`
+// if the calldata is too short to have it.
+fn replace_arg(input: &mut String, arg: &ArgReplacement) -> Option<String> {
+    // 0x + 4 byte selector, then 32 byte words
+    let start = 10 + arg.index * 64;
+    let before = format!("0x{}", input.get(start..start + 64)?);
+
+    let value = format!("{:0>64}", arg.value.trim_start_matches("0x"));
+    input.replace_range(start..start + 64, &value);
+
+    Some(before)
+}
@@ -1,10 +1,21 @@
+use crate::replay::remediate::remediate;
+use crate::replay::report::{
+    BlockOutcome,
+    FailedTransaction,
+    FailureReason,
+    Rewrite,
+};
+use crate::replay::resign::Resigner;
+use crate::replay::rewrite::Rewriter;
+use crate::replay::types::ReplayConfig;
 use crate::rpc::error::RequestError;
+use crate::rpc::format::hex_to_decimal;
 use crate::rpc::types::Transaction;
 use crate::RpcConnection;
 
 // Abstract over the return types of send functions
 impl RpcConnection {
-    async fn send(
+    pub async fn send(
         &self,
         tx: Transaction,
         chain_id: u64,
@@ -18,38 +29,166 @@ impl RpcConnection {
     }
 }
 
+// Send a tx as-is, re-signed if we have a resigner, or from the impersonated
+// sender if we rewrote it and the original signature doesn't match anymore.
+async fn send_one(
+    replay_rpc: &RpcConnection,
+    tx: &Transaction,
+    chain_id: u64,
+    send_as_unsigned: bool,
+    resigner: &mut Option<Resigner>,
+    impersonate: bool,
+) -> Result<String, RequestError> {
+    if impersonate {
+        replay_rpc
+            .anvil_impersonate_account(tx.from.clone())
+            .await?;
+        return replay_rpc.send_impersonated_transaction(tx.clone()).await;
+    }
+
+    match resigner {
+        Some(resigner) => resigner.send(replay_rpc, tx, chain_id).await,
+        None => {
+            replay_rpc
+                .send(tx.clone(), chain_id, send_as_unsigned)
+                .await
+        }
+    }
+}
+
 // Generic function we use to replay all tx in a block.
+// Returns the txs that failed to send, and the state patches and rewrites we
+// applied so they can go in the replay report.
 pub async fn send_transactions(
     replay_rpc: RpcConnection,
     historical_txs: Vec<Transaction>,
     chain_id: u64,
-    entropy_threshold: f32,
-    exit_on_tx_fail: bool,
-    send_as_unsigned: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let tx_amount = historical_txs.len() as f32;
-    let mut fail_tx_amount: f32 = 0.0;
+    config: &ReplayConfig,
+    resigner: &mut Option<Resigner>,
+    rewriter: &Option<Rewriter>,
+) -> Result<BlockOutcome, Box<dyn std::error::Error>> {
+    let tx_amount = historical_txs.len();
+    let mut outcome = BlockOutcome::default();
 
     for tx in historical_txs {
+        let (tx, rewritten) = match rewriter {
+            Some(rewriter) => {
+                match rewriter.rewrite(&tx) {
+                    Ok(Some((rewritten, rewrite))) => {
+                        println!("Rewrote {}: {}", tx.hash, rewrite.changes.join(", "));
+                        outcome.rewrites.push(rewrite);
+                        (rewritten, true)
+                    }
+                    Ok(None) => (tx, false),
+                    // A rule that doesn't fit this tx shouldn't stop the replay, send it as is
+                    Err(e) => {
+                        println!(
+                            "!!! \x1b[93mWARNING:\x1b[0m Could not rewrite {}, sending it unchanged: {} !!!",
+                            tx.hash, e
+                        );
+                        outcome.rewrites.push(Rewrite {
+                            block_number: hex_to_decimal(&tx.blockNumber).unwrap_or_default(),
+                            hash: Some(tx.hash.clone()),
+                            changes: vec![format!("skipped: {}", e)],
+                        });
+                        (tx, false)
+                    }
+                }
+            }
+            None => (tx, false),
+        };
+
+        let result = send_one(
+            &replay_rpc,
+            &tx,
+            chain_id,
+            config.send_as_unsigned,
+            resigner,
+            rewritten,
+        )
+        .await;
+
         // Gracefully handle errors so execution doesn't halt on error
-        match replay_rpc.send(tx, chain_id, send_as_unsigned).await {
-            Ok(_) => (),
-            Err(e) => {
-                if exit_on_tx_fail {
-                    return Err(e.into());
-                } else {
-                    fail_tx_amount += 1.0;
-                    println!("!!! \x1b[93mError sending transaction:\x1b[0m {} !!!", e)
+        let mut error = match result {
+            Ok(hash) => {
+                outcome.hashes.push(hash);
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        // Patch the sender and resend once if we know what went wrong
+        let mut remediation_error = None;
+        if config.remediate {
+            let sender = match resigner {
+                Some(resigner) if !rewritten => resigner.signer_of(&tx.from)?,
+                _ => tx.from.clone(),
+            };
+            let reason = FailureReason::classify(&error.to_string());
+
+            let patch = match remediate(&replay_rpc, &sender, &tx, reason).await {
+                Ok(patch) => patch,
+                // The node might not support the cheat RPCs we patch with
+                Err(e) => {
+                    println!(
+                        "!!! \x1b[93mWARNING:\x1b[0m Could not patch the sender of {}: {} !!!",
+                        tx.hash, e
+                    );
+                    remediation_error = Some(e);
+                    None
+                }
+            };
+            if let Some(patch) = patch {
+                println!(
+                    "Patched {} of {} from {} to {}, resending {}",
+                    patch.kind.as_str(),
+                    patch.address,
+                    patch.before,
+                    patch.after,
+                    tx.hash
+                );
+                outcome.patches.push(patch);
+
+                match send_one(
+                    &replay_rpc,
+                    &tx,
+                    chain_id,
+                    config.send_as_unsigned,
+                    resigner,
+                    rewritten,
+                )
+                .await
+                {
+                    Ok(hash) => {
+                        outcome.hashes.push(hash);
+                        continue;
+                    }
+                    Err(e) => error = e,
                 }
             }
         }
+
+        if config.exit_on_tx_fail {
+            return Err(error.into());
+        }
+        println!(
+            "!!! \x1b[93mError sending transaction:\x1b[0m {} !!!",
+            error
+        );
+        let message = match remediation_error {
+            Some(remediation_error) => {
+                format!("{}, remediation failed: {}", error, remediation_error)
+            }
+            None => error.to_string(),
+        };
+        outcome.failures.push(FailedTransaction::new(&tx, message));
     }
 
     // Calculate the percentage of failed transactions
-    let fail_percent = fail_tx_amount / tx_amount;
-    if fail_percent > entropy_threshold {
-        println!("!!! \x1b[91mHigh entropy detected!\x1b[0m Fail ratio: {}. Consider restarting the fork\x1b[0m !!!", format!("{:.2}%", fail_percent * 100.0));
+    let fail_percent = outcome.failures.len() as f32 / tx_amount as f32;
+    if fail_percent > config.entropy_threshold {
+        println!("!!! \x1b[91mHigh entropy detected!\x1b[0m Fail ratio: {:.2}%. Consider restarting the fork\x1b[0m !!!", fail_percent * 100.0);
     }
 
-    Ok(())
+    Ok(outcome)
 }
@@ -1,14 +1,48 @@
+use std::fs;
+use std::process::Command;
+use std::str::FromStr;
+
+use ethers::abi::param_type::Reader;
+use ethers::abi::token::{
+    LenientTokenizer,
+    Tokenizer,
+};
+use ethers::types::{
+    H160,
+    U256,
+};
+use ethers::utils::{
+    get_contract_address,
+    hex,
+};
+
+use crate::replay::types::*;
+use crate::rpc::format::hex_to_decimal;
 use crate::RpcConnection;
 
-// We use this to prepare the local node for replaying transactions.
-// Sets the mining params, and waits for the user to start replaying.
-pub async fn contract_setup(replay_rpc: RpcConnection) -> Result<(), Box<dyn std::error::Error>> {
-    // set automine to false
-    replay_rpc.evm_set_automine(false).await?;
-    // set insanely high interval for the blocks
-    replay_rpc
-        .evm_set_interval_mining(std::u32::MAX.into())
-        .await?;
+// We use this to prepare the local nodes for replaying transactions.
+// Sets the mining params, and applies `setup_file` if we have one. Otherwise
+// waits for the user to start replaying.
+pub async fn contract_setup(
+    replay_rpcs: &[RpcConnection],
+    setup_file: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for replay_rpc in replay_rpcs {
+        // set automine to false
+        replay_rpc.evm_set_automine(false).await?;
+        // set insanely high interval for the blocks
+        replay_rpc.evm_set_interval_mining(u32::MAX.into()).await?;
+    }
+
+    if let Some(setup_file) = setup_file {
+        for replay_rpc in replay_rpcs {
+            println!("Applying setup file {} to {}", setup_file, replay_rpc.url());
+            let setup: SetupFile = serde_json::from_str(&fs::read_to_string(&setup_file)?)?;
+            apply_setup(replay_rpc, setup).await?;
+        }
+        println!("Starting replay...");
+        return Ok(());
+    }
 
     // Wait for user input from keyboard to proceed
     println!("Please deploy your contracts, and prepare to start replaying.");
@@ -20,3 +54,123 @@ pub async fn contract_setup(replay_rpc: RpcConnection) -> Result<(), Box<dyn std
 
     Ok(())
 }
+
+async fn apply_setup(
+    replay_rpc: &RpcConnection,
+    setup: SetupFile,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for address in setup.impersonate {
+        replay_rpc
+            .anvil_impersonate_account(address.clone())
+            .await?;
+        println!("Impersonating {}", address);
+    }
+
+    for (address, balance) in setup.balances {
+        let balance = match balance.starts_with("0x") {
+            true => U256::from_str(&balance)?,
+            false => U256::from_dec_str(&balance)?,
+        };
+        replay_rpc
+            .anvil_set_balance(address.clone(), balance)
+            .await?;
+        println!("Set balance of {} to {}", address, balance);
+    }
+
+    for (address, code) in setup.code {
+        replay_rpc.anvil_set_code(address.clone(), code).await?;
+        println!("Set code of {}", address);
+    }
+
+    for write in setup.storage {
+        // Storage values have to be a full 32 byte word
+        let value = format!("0x{:0>64}", write.value.trim_start_matches("0x"));
+        replay_rpc
+            .anvil_set_storage_at(write.address.clone(), write.slot.clone(), value)
+            .await?;
+        println!("Set storage slot {} of {}", write.slot, write.address);
+    }
+
+    for deployment in setup.deploy {
+        deploy(replay_rpc, deployment).await?;
+    }
+
+    if setup.commands.is_empty() {
+        return Ok(());
+    }
+
+    // Commands like `forge script --broadcast` or `cast send` wait for their txs to be mined
+    replay_rpc.evm_set_automine(true).await?;
+    let first_block = hex_to_decimal(&replay_rpc.block_number().await?)?;
+
+    for command in &setup.commands {
+        println!("Running: {}", command);
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("REPLAY_RPC", replay_rpc.url())
+            .status()?;
+        if !status.success() {
+            return Err(format!("Setup command `{}` failed with {}", command, status).into());
+        }
+    }
+
+    replay_rpc.evm_set_automine(false).await?;
+    replay_rpc.evm_set_interval_mining(u32::MAX.into()).await?;
+
+    let last_block = hex_to_decimal(&replay_rpc.block_number().await?)?;
+    if last_block > first_block && !setup.allow_mining {
+        return Err(format!(
+            "Setup commands mined {} block(s), so blocks until {} would not be replayed. Fork the replay node that many blocks earlier and set \"allow_mining\": true in the setup file to continue anyway",
+            last_block - first_block,
+            last_block
+        )
+        .into());
+    }
+    if last_block > first_block {
+        println!(
+            "!!! \x1b[93mWARNING:\x1b[0m Setup commands mined {} block(s), replaying continues from block {} !!!",
+            last_block - first_block,
+            last_block + 1
+        );
+    }
+
+    Ok(())
+}
+
+// Send the creation tx of a contract. As automine is off and mining a block now would
+// shift the replay node off the historical block numbers, the contract gets deployed
+// in the first replayed block, before any historical tx, unless a setup command mines
+// a block before that.
+async fn deploy(
+    replay_rpc: &RpcConnection,
+    deployment: Deployment,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut tokens = Vec::new();
+    for arg in &deployment.args {
+        let param_type = Reader::read(&arg.arg_type)?;
+        tokens.push(LenientTokenizer::tokenize(&param_type, &arg.value)?);
+    }
+
+    let bytecode = deployment.bytecode.trim_start_matches("0x");
+    let data = format!(
+        "0x{}{}",
+        bytecode,
+        hex::encode(ethers::abi::encode(&tokens))
+    );
+
+    let nonce = replay_rpc
+        .get_transaction_count(deployment.from.clone(), "pending".to_string())
+        .await?;
+    let address = get_contract_address(H160::from_str(&deployment.from)?, hex_to_decimal(&nonce)?);
+
+    let hash = replay_rpc
+        .send_transaction(deployment.from, None, data)
+        .await?;
+    println!(
+        "Sent deployment {}, the contract will be at {:?} once the first block is replayed",
+        hash, address
+    );
+
+    Ok(())
+}
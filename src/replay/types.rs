@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+// Options shared by the historic and live replay modes.
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    pub replay_delay: u64,
+    // Reproduce historical block times scaled by this, instead of `replay_delay`
+    pub replay_speed: Option<f64>,
+    pub entropy_threshold: f32,
+    pub exit_on_tx_fail: bool,
+    pub send_as_unsigned: bool,
+    pub preserve_order: bool,
+    // Re-sign txs with keys from this file and/or derived from this seed
+    pub key_file: Option<String>,
+    pub key_seed: Option<String>,
+    pub hash_map_file: String,
+    // Patch sender balances and nonces to get failed txs through
+    pub remediate: bool,
+    // Write failed sends and entropy stats to this file
+    pub report_file: Option<String>,
+    // Apply the what-if rewrites of this file to the replayed txs
+    pub rewrite_file: Option<String>,
+    // Write blocks where the replay nodes disagree to this file
+    pub divergence_file: Option<String>,
+}
+
+impl ReplayConfig {
+    pub fn resign(&self) -> bool {
+        self.key_file.is_some() || self.key_seed.is_some()
+    }
+}
+
+// How many of the latest replayed blocks live replay can roll back on a reorg.
+pub const MAX_REORG_DEPTH: usize = 64;
+
+// How many times live replay fetches a block that doesn't extend the replayed chain again,
+// before giving up on the source.
+pub const MAX_REORG_RETRIES: u32 = 5;
+
+// How many polls of the source in a row mempool mode retries before giving up.
+pub const MAX_POLL_RETRIES: u32 = 5;
+
+// How often live replay rewrites the report files.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+// A block replayed in live mode, and the snapshot of the replay node right before it.
+#[derive(Debug, Clone)]
+pub struct ReplayedBlock {
+    pub number: u64,
+    pub hash: String,
+    pub parent_hash: String,
+    // One snapshot per replay node. Anvil only frees snapshots when reverting to them, so we
+    // only take one every `MAX_REORG_DEPTH` blocks.
+    pub snapshot_ids: Option<Vec<String>>,
+}
+
+// Declarative replacement for the interactive setup prompt, loaded from `--setup_file`.
+// Everything is applied to the replay node in the order of the fields.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SetupFile {
+    pub impersonate: Vec<String>,
+    // Address to balance in wei, as hex or decimal
+    pub balances: BTreeMap<String, String>,
+    // Address to runtime bytecode
+    pub code: BTreeMap<String, String>,
+    pub storage: Vec<StorageWrite>,
+    pub deploy: Vec<Deployment>,
+    // Ran with `sh -c`, with `REPLAY_RPC` set to the url of the replay node
+    pub commands: Vec<String>,
+    // Let commands mine blocks, which moves the replay past the historical block numbers
+    pub allow_mining: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StorageWrite {
+    pub address: String,
+    pub slot: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Deployment {
+    // Has to be unlocked or impersonated on the replay node
+    pub from: String,
+    pub bytecode: String,
+    #[serde(default)]
+    pub args: Vec<ConstructorArg>,
+}
+
+// A constructor argument, e.g. `{"type": "uint256", "value": "1"}`.
+#[derive(Debug, Deserialize)]
+pub struct ConstructorArg {
+    #[serde(rename = "type")]
+    pub arg_type: String,
+    pub value: String,
+}
+
+// What-if modifications applied while replaying, loaded from `--rewrite_file`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RewriteFile {
+    pub rules: Vec<RewriteRule>,
+    pub code: Vec<CodeSwap>,
+}
+
+// Txs matching every set field of `matcher` get the set fields of `replace`.
+#[derive(Debug, Deserialize)]
+pub struct RewriteRule {
+    #[serde(rename = "match")]
+    pub matcher: TxMatcher,
+    pub replace: TxReplacement,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TxMatcher {
+    pub hash: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // First 4 bytes of the calldata, e.g. `0xa9059cbb`
+    pub selector: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TxReplacement {
+    pub to: Option<String>,
+    pub value: Option<String>,
+    pub gas: Option<String>,
+    pub input: Option<String>,
+    // Replace single ABI encoded arguments of the calldata
+    pub args: Vec<ArgReplacement>,
+}
+
+// Replace the `index`th 32 byte word after the selector with `value`.
+#[derive(Debug, Deserialize)]
+pub struct ArgReplacement {
+    pub index: usize,
+    pub value: String,
+}
+
+// Replace the code of `address` right before replaying `block`.
+#[derive(Debug, Deserialize)]
+pub struct CodeSwap {
+    pub block: u64,
+    pub address: String,
+    pub code: String,
+}
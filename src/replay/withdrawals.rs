@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use ethers::types::U256;
+
+use crate::rpc::types::Withdrawal;
+use crate::RpcConnection;
+
+// Withdrawal amounts are denominated in gwei.
+const GWEI: u64 = 1_000_000_000;
+
+// Credit beacon chain withdrawals to their recipients on the replay node.
+// Withdrawals are processed at the end of a block outside of any transaction,
+// so we call this right after mining the block they belong to.
+pub async fn apply_withdrawals(
+    replay_rpc: &RpcConnection,
+    withdrawals: &[Withdrawal],
+) -> Result<(), Box<dyn std::error::Error>> {
+    // A single address can receive multiple withdrawals in the same block,
+    // sum them up so we only touch each balance once
+    let mut credits: BTreeMap<String, U256> = BTreeMap::new();
+    for withdrawal in withdrawals {
+        let amount = U256::from_str(&withdrawal.amount)? * GWEI;
+        *credits
+            .entry(withdrawal.address.to_lowercase())
+            .or_default() += amount;
+    }
+
+    for (address, amount) in credits {
+        let balance = replay_rpc
+            .get_balance(address.clone(), "latest".to_string())
+            .await?;
+        replay_rpc
+            .anvil_set_balance(address, balance + amount)
+            .await?;
+    }
+
+    Ok(())
+}
@@ -12,11 +12,7 @@ pub fn format_hex(hex: &str) -> &str {
 
 pub fn hex_to_decimal(hex_string: &str) -> Result<u64, std::num::ParseIntError> {
     // remove 0x prefix if it exists
-    let hex_string = if hex_string.starts_with("0x") {
-        &hex_string[2..]
-    } else {
-        hex_string
-    };
+    let hex_string = hex_string.strip_prefix("0x").unwrap_or(hex_string);
 
     u64::from_str_radix(hex_string, 16)
 }
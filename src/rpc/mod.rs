@@ -1,4 +1,5 @@
 pub mod error;
 pub mod format;
+#[allow(clippy::module_inception)]
 pub mod rpc;
 pub mod types;
@@ -1,3 +1,4 @@
+use std::str::FromStr;
 use std::thread::sleep;
 use std::time::Instant;
 use url::Url;
@@ -51,6 +52,10 @@ impl RpcConnection {
         }
     }
 
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
     // Generic fn to send rpc
     async fn send_request(&self, method: &str, mut params: Value) -> Result<String, RequestError> {
         // We do this because eth rpc cries if param is empty
@@ -59,12 +64,12 @@ impl RpcConnection {
             params = json!([]);
         }
 
-        let request: Value = json!({
-            "method": method.to_string(),
-            "params": params,
-            "id": 1,
-            "jsonrpc": "2.0".to_string(),
-        });
+        let request = JsonRpcRequest {
+            method: method.to_string(),
+            params,
+            id: 1,
+            jsonrpc: "2.0".to_string(),
+        };
 
         // #[cfg(debug_assertions)] {
         //     println!("Sending request: {}", request.clone());
@@ -92,6 +97,65 @@ impl RpcConnection {
         Ok(response.result.to_string())
     }
 
+    // Send several requests in one JSON-RPC batch, returns the results in the order of `requests`.
+    // The batch itself fails if the node doesn't support batching.
+    pub async fn send_batch(
+        &self,
+        requests: Vec<(&str, Value)>,
+    ) -> Result<Vec<Result<String, RequestError>>, RequestError> {
+        let batch: Vec<JsonRpcRequest> = requests
+            .into_iter()
+            .enumerate()
+            .map(|(id, (method, params))| {
+                JsonRpcRequest {
+                    method: method.to_string(),
+                    params,
+                    id: id as u32,
+                    jsonrpc: "2.0".to_string(),
+                }
+            })
+            .collect();
+        let batch_len = batch.len();
+
+        let response = match self.client.post(&self.url).json(&batch).send().await {
+            Ok(response) => response,
+            Err(err) => return Err(RequestError::JsonSerializationFailed(err.to_string())),
+        };
+
+        let responses: Vec<Value> = match response.json().await {
+            Ok(responses) => responses,
+            Err(err) => return Err(RequestError::JsonDeserializationFailed(err.to_string())),
+        };
+        if responses.len() != batch_len {
+            return Err(RequestError::RequestFailed(format!(
+                "Sent {} requests in a batch but got {} responses",
+                batch_len,
+                responses.len()
+            )));
+        }
+
+        // Responses can come back in any order
+        let mut results: Vec<Result<String, RequestError>> = (0..batch_len)
+            .map(|_| Err(RequestError::RequestFailed("Missing response".to_string())))
+            .collect();
+        for response in responses {
+            let id = response["id"].as_u64().unwrap_or(u64::MAX) as usize;
+            if id >= batch_len {
+                continue;
+            }
+            results[id] = match response.get("result") {
+                Some(result) => Ok(result.to_string()),
+                None => {
+                    Err(RequestError::RequestFailed(
+                        response["error"]["message"].to_string(),
+                    ))
+                }
+            };
+        }
+
+        Ok(results)
+    }
+
     /*
      * JSON-RPC methods
      */
@@ -120,6 +184,18 @@ impl RpcConnection {
         self.send_request("eth_getBlockByNumber", params).await
     }
 
+    // Gets the receipts of all txs in a block.
+    pub async fn get_block_receipts(&self, block_number: String) -> Result<String, RequestError> {
+        let params = json!([block_number]);
+        self.send_request("eth_getBlockReceipts", params).await
+    }
+
+    // Gets the receipt of a tx by its hash.
+    pub async fn get_transaction_receipt(&self, tx_hash: String) -> Result<String, RequestError> {
+        let params = json!([tx_hash]);
+        self.send_request("eth_getTransactionReceipt", params).await
+    }
+
     // Gets storage at address and slot for the latest block
     pub async fn get_storage_at(
         &self,
@@ -145,6 +221,28 @@ impl RpcConnection {
         Ok(result.trim_matches('\"').to_string())
     }
 
+    // Gets the balance of an address at the block specified in the argument
+    pub async fn get_balance(&self, address: String, block: String) -> Result<U256, RequestError> {
+        let params = json!([address, block]);
+        let result = self.send_request("eth_getBalance", params).await?;
+
+        match U256::from_str(result.trim_matches('\"')) {
+            Ok(balance) => Ok(balance),
+            Err(err) => Err(RequestError::JsonDeserializationFailed(err.to_string())),
+        }
+    }
+
+    // Gets the nonce of an address at the block specified in the argument
+    pub async fn get_transaction_count(
+        &self,
+        address: String,
+        block: String,
+    ) -> Result<String, RequestError> {
+        let params = json!([address, block]);
+        let nonce = self.send_request("eth_getTransactionCount", params).await?;
+        Ok(format_hex(&nonce).to_string())
+    }
+
     // Gets transaction by hash (duh).
     pub async fn get_transaction_by_hash(&self, tx_hash: String) -> Result<String, RequestError> {
         let params = json!([tx_hash]);
@@ -165,6 +263,13 @@ impl RpcConnection {
         self.send_request("eth_sendRawTransaction", params).await
     }
 
+    // Sends an already signed and rlp encoded transaction
+    pub async fn send_raw_transaction_rlp(&self, rlp: String) -> Result<String, RequestError> {
+        let params = json!([rlp]);
+        let hash = self.send_request("eth_sendRawTransaction", params).await?;
+        Ok(format_hex(&hash).to_string())
+    }
+
     // Sends raw transaction
     pub async fn call(&self, tx: CallParams, block_number: String) -> Result<String, RequestError> {
         // TODO: maybe value?
@@ -188,7 +293,10 @@ impl RpcConnection {
             from: tx.from,
             to: tx.to,
             gas: tx.gas,
-            gasPrice: tx.gasPrice,
+            gasPrice: Some(tx.gasPrice),
+            maxFeePerGas: None,
+            maxPriorityFeePerGas: None,
+            accessList: None,
             value: tx.value,
             data: tx.input,
             nonce: Some(tx.nonce),
@@ -200,6 +308,34 @@ impl RpcConnection {
             .await
     }
 
+    // Send tx from an impersonated sender, the node fills in the signature
+    pub async fn send_impersonated_transaction(
+        &self,
+        tx: Transaction,
+    ) -> Result<String, RequestError> {
+        // Keep the fee fields of EIP-1559 txs, so the tx pays and gets ordered like the original
+        let tx = TransactionParams {
+            from: tx.from,
+            to: tx.to,
+            gas: tx.gas,
+            gasPrice: match tx.maxFeePerGas {
+                Some(_) => None,
+                None => Some(tx.gasPrice),
+            },
+            maxFeePerGas: tx.maxFeePerGas,
+            maxPriorityFeePerGas: tx.maxPriorityFeePerGas,
+            accessList: tx.accessList,
+            value: tx.value,
+            data: tx.input,
+            nonce: Some(tx.nonce),
+            chainId: None,
+        };
+
+        let params = json!([tx]);
+        let hash = self.send_request("eth_sendTransaction", params).await?;
+        Ok(format_hex(&hash).to_string())
+    }
+
     // Turn automining on/off. If on, mines on every tx.
     pub async fn evm_set_automine(&self, mode: bool) -> Result<String, RequestError> {
         let params = json!([mode]);
@@ -226,6 +362,122 @@ impl RpcConnection {
         self.send_request("evm_setNextBlockTimestamp", params).await
     }
 
+    // Set the coinbase address used for the next blocks.
+    pub async fn anvil_set_coinbase(&self, address: String) -> Result<String, RequestError> {
+        let params = json!([address]);
+        self.send_request("anvil_setCoinbase", params).await
+    }
+
+    // Set the base fee of the next block.
+    pub async fn anvil_set_next_block_base_fee_per_gas(
+        &self,
+        base_fee: String,
+    ) -> Result<String, RequestError> {
+        let params = json!([base_fee]);
+        self.send_request("anvil_setNextBlockBaseFeePerGas", params)
+            .await
+    }
+
+    // Set the gas limit used for the next blocks.
+    pub async fn evm_set_block_gas_limit(&self, gas_limit: String) -> Result<String, RequestError> {
+        let params = json!([gas_limit]);
+        self.send_request("evm_setBlockGasLimit", params).await
+    }
+
+    // Set the prevrandao value of the next block.
+    pub async fn anvil_set_prev_randao(&self, prev_randao: String) -> Result<String, RequestError> {
+        let params = json!([prev_randao]);
+        self.send_request("anvil_setPrevRandao", params).await
+    }
+
+    // Set the balance of an address.
+    pub async fn anvil_set_balance(
+        &self,
+        address: String,
+        balance: U256,
+    ) -> Result<String, RequestError> {
+        let params = json!([address, balance]);
+        self.send_request("anvil_setBalance", params).await
+    }
+
+    // Set the nonce of an address.
+    pub async fn anvil_set_nonce(
+        &self,
+        address: String,
+        nonce: String,
+    ) -> Result<String, RequestError> {
+        let params = json!([address, nonce]);
+        self.send_request("anvil_setNonce", params).await
+    }
+
+    // Replace the code at an address.
+    pub async fn anvil_set_code(
+        &self,
+        address: String,
+        code: String,
+    ) -> Result<String, RequestError> {
+        let params = json!([address, code]);
+        self.send_request("anvil_setCode", params).await
+    }
+
+    // Write a value into a storage slot of an address.
+    pub async fn anvil_set_storage_at(
+        &self,
+        address: String,
+        slot: String,
+        value: String,
+    ) -> Result<String, RequestError> {
+        let params = json!([address, slot, value]);
+        self.send_request("anvil_setStorageAt", params).await
+    }
+
+    // Send txs from an address without having its key.
+    pub async fn anvil_impersonate_account(&self, address: String) -> Result<String, RequestError> {
+        let params = json!([address]);
+        self.send_request("anvil_impersonateAccount", params).await
+    }
+
+    // Send a tx from an unlocked or impersonated account, returns the tx hash.
+    pub async fn send_transaction(
+        &self,
+        from: String,
+        to: Option<String>,
+        data: String,
+    ) -> Result<String, RequestError> {
+        let params = json!([{
+            "from": from,
+            "to": to,
+            "data": data,
+        }]);
+        let hash = self.send_request("eth_sendTransaction", params).await?;
+        Ok(format_hex(&hash).to_string())
+    }
+
+    // Snapshot the state of the node, returns the snapshot id.
+    pub async fn evm_snapshot(&self) -> Result<String, RequestError> {
+        let id = self
+            .send_request("evm_snapshot", serde_json::Value::Null)
+            .await?;
+        Ok(format_hex(&id).to_string())
+    }
+
+    // Revert the state of the node to a snapshot.
+    pub async fn evm_revert(&self, snapshot_id: String) -> Result<String, RequestError> {
+        let params = json!([snapshot_id]);
+        self.send_request("evm_revert", params).await
+    }
+
+    // Gets anvil node info. Contains the transaction ordering mode of the node.
+    pub async fn anvil_node_info(&self) -> Result<Value, RequestError> {
+        let info = self
+            .send_request("anvil_nodeInfo", serde_json::Value::Null)
+            .await?;
+        match serde_json::from_str(&info) {
+            Ok(info) => Ok(info),
+            Err(err) => Err(RequestError::JsonDeserializationFailed(err.to_string())),
+        }
+    }
+
     // Gets hardhat mining mode. We use this to check if our node is HH or anvil.
     pub async fn hardhat_get_automine(&self) -> Result<String, RequestError> {
         self.send_request("hardhat_getAutomine", serde_json::Value::Null)
@@ -269,6 +521,91 @@ impl RpcConnection {
         Ok(new_blocknumber)
     }
 
+    // Gets the pending and queued txs in the mempool of the node, grouped by sender and nonce.
+    pub async fn txpool_content(&self) -> Result<Value, RequestError> {
+        let content = self
+            .send_request("txpool_content", serde_json::Value::Null)
+            .await?;
+        serde_json::from_str(&content)
+            .map_err(|e| RequestError::JsonDeserializationFailed(e.to_string()))
+    }
+
+    // Create a filter for txs entering the mempool, returns the filter id.
+    pub async fn new_pending_transaction_filter(&self) -> Result<String, RequestError> {
+        let id = self
+            .send_request("eth_newPendingTransactionFilter", serde_json::Value::Null)
+            .await?;
+        Ok(format_hex(&id).to_string())
+    }
+
+    // Gets the hashes of the pending txs seen since the last time we asked.
+    pub async fn get_filter_changes(&self, filter_id: String) -> Result<Vec<String>, RequestError> {
+        let params = json!([filter_id]);
+        let changes = self.send_request("eth_getFilterChanges", params).await?;
+        serde_json::from_str(&changes)
+            .map_err(|e| RequestError::JsonDeserializationFailed(e.to_string()))
+    }
+
+    // Gets the traces of every call to `to_address` between `from_block` and `to_block` (inclusive).
+    // Only nodes with the `trace` namespace (erigon, reth, nethermind) support this.
+    pub async fn trace_filter(
+        &self,
+        from_block: String,
+        to_block: String,
+        to_address: String,
+    ) -> Result<Vec<Value>, RequestError> {
+        let params = json!([{
+            "fromBlock": from_block,
+            "toBlock": to_block,
+            "toAddress": [to_address],
+        }]);
+        let traces = self.send_request("trace_filter", params).await?;
+        serde_json::from_str(&traces)
+            .map_err(|e| RequestError::JsonDeserializationFailed(e.to_string()))
+    }
+
+    // What every tx of a block changed, traced with the prestate tracer in diff mode.
+    pub async fn trace_block_state_diff(
+        &self,
+        block_number: String,
+    ) -> Result<Vec<Value>, RequestError> {
+        let params = json!([block_number, {
+            "tracer": "prestateTracer",
+            "tracerConfig": { "diffMode": true },
+        }]);
+        let traces = self
+            .send_request("debug_traceBlockByNumber", params)
+            .await?;
+        serde_json::from_str(&traces)
+            .map_err(|e| RequestError::JsonDeserializationFailed(e.to_string()))
+    }
+
+    // The state a call reads, as the accounts and storage slots it touches, traced with
+    // the prestate tracer.
+    pub async fn trace_call_prestate(
+        &self,
+        tx: CallParams,
+        block_number: String,
+    ) -> Result<Value, RequestError> {
+        let params = json!([tx, block_number, { "tracer": "prestateTracer" }]);
+        let prestate = self.send_request("debug_traceCall", params).await?;
+        serde_json::from_str(&prestate)
+            .map_err(|e| RequestError::JsonDeserializationFailed(e.to_string()))
+    }
+
+    // What every tx of a block changed, from the `trace` namespace.
+    pub async fn replay_block_state_diff(
+        &self,
+        block_number: String,
+    ) -> Result<Vec<Value>, RequestError> {
+        let params = json!([block_number, ["stateDiff"]]);
+        let traces = self
+            .send_request("trace_replayBlockTransactions", params)
+            .await?;
+        serde_json::from_str(&traces)
+            .map_err(|e| RequestError::JsonDeserializationFailed(e.to_string()))
+    }
+
     /*
      * Helper functions
      */
@@ -1,3 +1,7 @@
+use ethers::signers::{
+    LocalWallet,
+    Signer,
+};
 use ethers::types::transaction::eip2930::AccessList;
 use serde::{
     Deserialize,
@@ -24,17 +28,18 @@ use crate::hex_to_decimal;
 #[derive(Debug, Deserialize, Serialize)]
 #[allow(dead_code, non_snake_case)]
 pub struct BlockResult {
-    difficulty: Option<String>,
+    pub baseFeePerGas: Option<String>,
+    pub difficulty: Option<String>,
     extraData: String,
-    gasLimit: String,
+    pub gasLimit: String,
     gasUsed: String,
-    hash: String,
+    pub hash: String,
     logsBloom: String,
-    miner: String,
-    mixHash: String,
+    pub miner: String,
+    pub mixHash: String,
     nonce: String,
-    number: String,
-    parentHash: String,
+    pub number: String,
+    pub parentHash: String,
     receiptsRoot: String,
     sha3Uncles: String,
     size: String,
@@ -44,6 +49,21 @@ pub struct BlockResult {
     pub transactions: Vec<Transaction>,
     transactionsRoot: String,
     uncles: Vec<String>,
+    pub withdrawals: Option<Vec<Withdrawal>>,
+    pub withdrawalsRoot: Option<String>,
+    pub blobGasUsed: Option<String>,
+    pub excessBlobGas: Option<String>,
+    pub parentBeaconBlockRoot: Option<String>,
+}
+
+// Beacon chain withdrawal, `amount` is denominated in gwei.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[allow(non_snake_case)]
+pub struct Withdrawal {
+    pub index: String,
+    pub validatorIndex: String,
+    pub address: String,
+    pub amount: String,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -73,19 +93,58 @@ pub struct Transaction {
 
 impl Transaction {
     pub fn rlp_serialize_tx(&self, chain_id: u64) -> Result<String, Box<dyn std::error::Error>> {
-        let encoded;
         // if access list exists we need the typed transaction to be an eip1559 one
-        if self.maxFeePerGas.is_some() {
-            encoded = self.rlp_serialize_eip1559(chain_id)?;
+        let encoded = if self.maxFeePerGas.is_some() {
+            self.rlp_serialize_eip1559(chain_id)?
         } else {
-            encoded = self.rlp_serialize_legacy(chain_id)?;
-        }
+            self.rlp_serialize_legacy(chain_id)?
+        };
 
         //println!("ENCODED: {:?}", hex::encode(typed_tx.rlp_signed(&sig)));
         Ok(encoded)
     }
 
+    // Sign the tx with `wallet` for `chain_id` instead of using the historical signature.
+    pub fn rlp_resign_tx(
+        &self,
+        wallet: &LocalWallet,
+        chain_id: u64,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let mut typed_tx = if self.maxFeePerGas.is_some() {
+            self.eip1559_request(chain_id)?
+        } else {
+            self.legacy_request(chain_id)?
+        };
+        typed_tx.set_from(wallet.address());
+
+        let sig = wallet.sign_transaction_sync(&typed_tx)?;
+        let encoded = hex::encode(typed_tx.rlp_signed(&sig));
+
+        Ok(format!("0x{}", encoded))
+    }
+
     fn rlp_serialize_eip1559(&self, chain_id: u64) -> Result<String, Box<dyn std::error::Error>> {
+        let typed_tx = self.eip1559_request(chain_id)?;
+        let sig = self.signature()?;
+
+        Ok(hex::encode(typed_tx.rlp_signed(&sig)))
+    }
+
+    fn rlp_serialize_legacy(&self, chain_id: u64) -> Result<String, Box<dyn std::error::Error>> {
+        let typed_tx = self.legacy_request(chain_id)?;
+        let sig = self.signature()?;
+
+        let encoded = hex::encode(typed_tx.rlp_signed(&sig));
+        // Add 0x prefix to encoded tx
+        let encoded = format!("0x{}", encoded);
+
+        Ok(encoded)
+    }
+
+    fn eip1559_request(
+        &self,
+        chain_id: u64,
+    ) -> Result<TypedTransaction, Box<dyn std::error::Error>> {
         let to = match &self.to {
             Some(_) => {
                 Some(ethers::types::NameOrAddress::Address(H160::from_str(
@@ -112,27 +171,13 @@ impl Transaction {
             chain_id: Some(chain_id.into()),
         };
 
-        let typed_tx = TypedTransaction::Eip1559(transaction);
-
-        // convert r and s to U256
-        // convert v to U64
-        // r, s and v are str's. it doesnt matter too much performance wise that we
-        // are converting it here since we are only using it here
-        let r: U256 = U256::from_str(&self.r)?;
-        let s: U256 = U256::from_str(&self.s)?;
-        let v: u64 = hex_to_decimal(&self.v)?;
-
-        // create a new use ethers::types::Signature with the r, s, and v values
-        let sig: Signature = Signature {
-            r, // as U256
-            s, // as U256
-            v, // as U64
-        };
-
-        Ok(hex::encode(typed_tx.rlp_signed(&sig)))
+        Ok(TypedTransaction::Eip1559(transaction))
     }
 
-    fn rlp_serialize_legacy(&self, chain_id: u64) -> Result<String, Box<dyn std::error::Error>> {
+    fn legacy_request(
+        &self,
+        chain_id: u64,
+    ) -> Result<TypedTransaction, Box<dyn std::error::Error>> {
         let to = match &self.to {
             Some(_) => {
                 Some(ethers::types::NameOrAddress::Address(H160::from_str(
@@ -155,8 +200,10 @@ impl Transaction {
             chain_id: Some(chain_id.into()),
         };
 
-        let typed_tx = TypedTransaction::Legacy(transaction);
+        Ok(TypedTransaction::Legacy(transaction))
+    }
 
+    fn signature(&self) -> Result<Signature, Box<dyn std::error::Error>> {
         // convert r and s to U256
         // convert v to U64
         // r, s and v are str's. it doesnt matter too much performance wise that we
@@ -166,17 +213,11 @@ impl Transaction {
         let v: u64 = hex_to_decimal(&self.v)?;
 
         // create a new use ethers::types::Signature with the r, s, and v values
-        let sig: Signature = Signature {
+        Ok(Signature {
             r, // as U256
             s, // as U256
             v, // as U64
-        };
-
-        let encoded = hex::encode(typed_tx.rlp_signed(&sig));
-        // Add 0x prefix to encoded tx
-        let encoded = format!("0x{}", encoded);
-
-        Ok(encoded)
+        })
     }
 }
 
@@ -187,13 +228,21 @@ pub struct TransactionParams {
     pub to: Option<String>,
     pub value: String,
     pub gas: String,
-    pub gasPrice: String,
+    // Left out for EIP-1559 txs, nodes reject txs that set both kinds of fees
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gasPrice: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxFeePerGas: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxPriorityFeePerGas: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accessList: Option<Vec<Value>>,
     pub data: String,
     pub nonce: Option<String>,
     pub chainId: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CallParams {
     pub from: serde_json::value::Value,
     pub to: String,
@@ -0,0 +1,279 @@
+use crate::rpc::format::decimal_to_hex;
+use crate::rpc::types::CallParams;
+use crate::tracker::fetch::Query;
+use crate::tracker::types::StateChange;
+use crate::RpcConnection;
+use serde_json::Value;
+
+use ethers::types::U256;
+
+// Where we get the state diffs of txs from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DiffSource {
+    // The prestate tracer of `debug_traceBlockByNumber`
+    Debug,
+    // `trace_replayBlockTransactions`
+    Trace,
+    // Neither works, so we only add timestamps
+    Unavailable,
+}
+
+// The state diffs of a block's txs, as the tx hash and the accounts it changed.
+type BlockDiffs = Vec<(String, Vec<Value>)>;
+
+// The storage a tracked value is read from, as accounts and their slots, or None for any
+// slot of the account.
+type ReadSet = Vec<(String, Option<Vec<U256>>)>;
+
+// Adds the block timestamp and the txs that wrote the tracked state to changes, for `--annotate`.
+pub struct Annotator<'a> {
+    source_rpc: &'a RpcConnection,
+    // Found out on the first change we annotate
+    source: Option<DiffSource>,
+    // The last block we annotated, targets of `multi_track` often change in the same one
+    cached: Option<(U256, Option<U256>, Option<BlockDiffs>)>,
+    // Whether `debug_traceCall` works on this node, found out on the first call we annotate
+    traces_calls: Option<bool>,
+}
+
+impl<'a> Annotator<'a> {
+    pub fn new(source_rpc: &'a RpcConnection) -> Self {
+        Self {
+            source_rpc,
+            source: None,
+            cached: None,
+            traces_calls: None,
+        }
+    }
+
+    // Annotate `change` of `query` on `address` with the txs in its block that wrote the
+    // storage it's read from. Annotations that fail are left out with a warning, they
+    // don't stop tracking.
+    pub async fn annotate(&mut self, change: &mut StateChange, address: &str, query: &Query) {
+        let block = decimal_to_hex(change.block_number.as_u64());
+
+        if self.cached.as_ref().map(|(block, _, _)| *block) != Some(change.block_number) {
+            let timestamp = match self.timestamp(&block).await {
+                Ok(timestamp) => Some(timestamp),
+                Err(e) => {
+                    println!("!!! \x1b[93mWARNING:\x1b[0m Couldn't get the timestamp of block {}: {} !!!", change.block_number, e);
+                    None
+                }
+            };
+
+            let diffs = match self.source {
+                Some(DiffSource::Debug) => self.debug_diffs(&block).await.map(Some),
+                Some(DiffSource::Trace) => self.trace_diffs(&block).await.map(Some),
+                Some(DiffSource::Unavailable) => Ok(None),
+                None => Ok(self.probe(&block).await),
+            };
+            let diffs = diffs.unwrap_or_else(|e| {
+                println!("!!! \x1b[93mWARNING:\x1b[0m Couldn't get the state diffs of block {}, its changes only get a timestamp: {} !!!", change.block_number, e);
+                None
+            });
+            self.cached = Some((change.block_number, timestamp, diffs));
+        }
+
+        let reads = match (query, &self.cached) {
+            (_, Some((_, _, None))) => None,
+            (Query::Slot(slot), _) => Some(vec![(address.to_string(), Some(vec![*slot]))]),
+            (Query::Call(tx), _) => {
+                // Without a trace of the call, it can depend on any of the contract's storage
+                Some(
+                    self.call_reads(tx, &block)
+                        .await
+                        .unwrap_or_else(|| vec![(address.to_string(), None)]),
+                )
+            }
+        };
+
+        let (_, timestamp, diffs) = self.cached.as_ref().expect("Block is cached");
+        change.timestamp = *timestamp;
+        change.transactions = diffs.as_ref().zip(reads).map(|(diffs, reads)| {
+            diffs
+                .iter()
+                .filter(|(_, accounts)| accounts.iter().any(|accounts| writes(accounts, &reads)))
+                .map(|(hash, _)| hash.clone())
+                .collect()
+        });
+    }
+
+    async fn timestamp(&self, block: &str) -> Result<U256, Box<dyn std::error::Error>> {
+        let header: Value = serde_json::from_str(
+            &self
+                .source_rpc
+                .get_block_by_number(block.to_string())
+                .await?,
+        )?;
+        let timestamp = header["timestamp"]
+            .as_str()
+            .ok_or(format!("Block {} has no timestamp", block))?;
+        Ok(U256::from_str_radix(timestamp, 16)?)
+    }
+
+    // The storage `tx` reads at `block`, from the prestate tracer of `debug_traceCall`.
+    async fn call_reads(&mut self, tx: &CallParams, block: &str) -> Option<ReadSet> {
+        if self.traces_calls == Some(false) {
+            return None;
+        }
+
+        let prestate = match self
+            .source_rpc
+            .trace_call_prestate(tx.clone(), block.to_string())
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|prestate| {
+                match prestate {
+                    Value::Object(accounts) => Ok(accounts),
+                    _ => Err(format!("Unexpected prestate {}", prestate)),
+                }
+            }) {
+            Ok(prestate) => prestate,
+            Err(e) if self.traces_calls.is_none() => {
+                println!("!!! \x1b[93mWARNING:\x1b[0m debug_traceCall is not available, calls get the transactions that wrote any storage of their contract: {} !!!", e);
+                self.traces_calls = Some(false);
+                return None;
+            }
+            Err(e) => {
+                println!("!!! \x1b[93mWARNING:\x1b[0m Couldn't trace the call at block {}, it gets the transactions that wrote any storage of its contract: {} !!!", block, e);
+                return None;
+            }
+        };
+        self.traces_calls = Some(true);
+
+        Some(
+            prestate
+                .iter()
+                .filter_map(|(account, state)| {
+                    let slots: Vec<U256> = state["storage"]
+                        .as_object()?
+                        .keys()
+                        .filter_map(|key| U256::from_str_radix(key, 16).ok())
+                        .collect();
+                    (!slots.is_empty()).then(|| (account.clone(), Some(slots)))
+                })
+                .collect(),
+        )
+    }
+
+    // Find a diff source that works on this node.
+    async fn probe(&mut self, block: &str) -> Option<BlockDiffs> {
+        if let Ok(diffs) = self.debug_diffs(block).await {
+            self.source = Some(DiffSource::Debug);
+            return Some(diffs);
+        }
+        if let Ok(diffs) = self.trace_diffs(block).await {
+            self.source = Some(DiffSource::Trace);
+            return Some(diffs);
+        }
+
+        println!("!!! \x1b[93mWARNING:\x1b[0m Neither debug_traceBlockByNumber nor trace_replayBlockTransactions are available, changes will only have timestamps !!!");
+        self.source = Some(DiffSource::Unavailable);
+        None
+    }
+
+    async fn debug_diffs(&self, block: &str) -> Result<BlockDiffs, Box<dyn std::error::Error>> {
+        let traces = self
+            .source_rpc
+            .trace_block_state_diff(block.to_string())
+            .await?;
+
+        // Slots that got cleared are only in `pre`
+        Ok(traces
+            .iter()
+            .filter_map(|trace| {
+                let hash = trace["txHash"].as_str()?.to_string();
+                Some((
+                    hash,
+                    vec![
+                        trace["result"]["pre"].clone(),
+                        trace["result"]["post"].clone(),
+                    ],
+                ))
+            })
+            .collect())
+    }
+
+    async fn trace_diffs(&self, block: &str) -> Result<BlockDiffs, Box<dyn std::error::Error>> {
+        let traces = self
+            .source_rpc
+            .replay_block_state_diff(block.to_string())
+            .await?;
+
+        Ok(traces
+            .iter()
+            .filter_map(|trace| {
+                let hash = trace["transactionHash"].as_str()?.to_string();
+                Some((hash, vec![trace["stateDiff"].clone()]))
+            })
+            .collect())
+    }
+}
+
+// Whether the accounts of a state diff have any of the storage in `reads` in them.
+fn writes(accounts: &Value, reads: &ReadSet) -> bool {
+    for (address, slots) in reads {
+        let storage = accounts
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(account, _)| account.eq_ignore_ascii_case(address))
+            .filter_map(|(_, account)| account["storage"].as_object());
+
+        for storage in storage {
+            let written = match slots {
+                Some(slots) => {
+                    storage.keys().any(|key| {
+                        U256::from_str_radix(key, 16)
+                            .ok()
+                            .is_some_and(|key| slots.contains(&key))
+                    })
+                }
+                None => !storage.is_empty(),
+            };
+            if written {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TOKEN: &str = "0x0000000000000000000000000000000000000001";
+    const ORACLE: &str = "0x0000000000000000000000000000000000000002";
+
+    #[test]
+    fn matches_writes_against_read_set() {
+        let accounts = json!({
+            "0x0000000000000000000000000000000000000001": {
+                "storage": {
+                    "0x0000000000000000000000000000000000000000000000000000000000000003": "0x1",
+                },
+            },
+        });
+
+        let slot = |slot: u64| vec![(TOKEN.to_string(), Some(vec![U256::from(slot)]))];
+        assert!(writes(&accounts, &slot(3)));
+        assert!(!writes(&accounts, &slot(4)));
+
+        // A call reading the written slot through another contract
+        let reads = vec![
+            (ORACLE.to_string(), Some(vec![U256::from(3)])),
+            (
+                TOKEN.to_uppercase().replace("0X", "0x"),
+                Some(vec![U256::from(3)]),
+            ),
+        ];
+        assert!(writes(&accounts, &reads));
+        assert!(!writes(&accounts, &reads[..1].to_vec()));
+
+        // Any slot of the contract, when the call couldn't be traced
+        assert!(writes(&accounts, &vec![(TOKEN.to_string(), None)]));
+        assert!(!writes(&accounts, &vec![(ORACLE.to_string(), None)]));
+    }
+}
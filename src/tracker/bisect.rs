@@ -0,0 +1,234 @@
+use crate::rpc::format::decimal_to_hex;
+use crate::tracker::decoder::StateDecoder;
+use crate::tracker::types::*;
+use crate::RpcConnection;
+
+use std::collections::{
+    BTreeSet,
+    VecDeque,
+};
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+
+use ethers::types::U256;
+
+// Finds the blocks where a storage slot changed by sampling the range and bisecting
+// the intervals whose ends differ, instead of querying every block.
+//
+// A slot that changes and changes back within a sampled interval (A -> B -> A) looks
+// unchanged at both ends, so those changes are missed unless `confirm_with_traces` is set.
+// Then every interval is also split at the blocks the contract was called in, which
+// catches them even if the ends differ (A -> B -> A -> C).
+pub struct Bisector<'a> {
+    source_rpc: &'a RpcConnection,
+    contract_address: &'a str,
+    storage_slot: U256,
+    decoder: &'a StateDecoder,
+    confirm_with_traces: bool,
+    // Sampled blocks we haven't searched up to yet
+    samples: VecDeque<u64>,
+    // The value at the last sample we searched up to
+    last: Option<StateChange>,
+    scanned: u64,
+    queries: u64,
+    trace_queries: u64,
+    // Storage queries made for the blocks `trace_filter` found, part of `queries`
+    confirm_queries: u64,
+}
+
+impl<'a> Bisector<'a> {
+    // Search `origin_block..terminal_block`, sampling every `sample_interval` blocks.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        source_rpc: &'a RpcConnection,
+        contract_address: &'a str,
+        storage_slot: U256,
+        decoder: &'a StateDecoder,
+        origin_block: u64,
+        terminal_block: u64,
+        sample_interval: u64,
+        confirm_with_traces: bool,
+    ) -> Self {
+        let mut samples: VecDeque<u64> = (origin_block..terminal_block)
+            .step_by(sample_interval.max(1) as usize)
+            .collect();
+        if samples.back() != Some(&(terminal_block - 1)) {
+            samples.push_back(terminal_block - 1);
+        }
+
+        Self {
+            source_rpc,
+            contract_address,
+            storage_slot,
+            decoder,
+            confirm_with_traces,
+            samples,
+            last: None,
+            scanned: terminal_block - origin_block,
+            queries: 0,
+            trace_queries: 0,
+            confirm_queries: 0,
+        }
+    }
+
+    async fn get(&mut self, block: u64) -> Result<StateChange, Box<dyn std::error::Error>> {
+        self.queries += 1;
+        let value = self
+            .source_rpc
+            .get_storage_at_block(
+                self.contract_address.to_string(),
+                self.storage_slot,
+                decimal_to_hex(block),
+            )
+            .await?;
+        self.decoder
+            .state_change(block.into(), TrackedValue::parse(&value))
+    }
+
+    // Blocks in `from..=to` where the contract was called, the only blocks its storage
+    // can change in.
+    async fn touched_blocks(
+        &mut self,
+        from: u64,
+        to: u64,
+    ) -> Result<BTreeSet<u64>, Box<dyn std::error::Error>> {
+        self.trace_queries += 1;
+        let traces = self
+            .source_rpc
+            .trace_filter(
+                decimal_to_hex(from),
+                decimal_to_hex(to),
+                self.contract_address.to_string(),
+            )
+            .await
+            .map_err(|e| format!("trace_filter failed, is the trace namespace enabled? {}", e))?;
+
+        Ok(traces
+            .iter()
+            .filter_map(|trace| trace["blockNumber"].as_u64())
+            .collect())
+    }
+
+    // Find the first block of every change between `start` and `end`, which are already known.
+    async fn bisect(
+        &mut self,
+        start: StateChange,
+        end: StateChange,
+        changes: &mut Vec<StateChange>,
+        interrupted: &AtomicBool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut intervals = vec![(start, end)];
+
+        while let Some((start, end)) = intervals.pop() {
+            if interrupted.load(Ordering::SeqCst) {
+                break;
+            }
+            if !self.decoder.differs(&start, &end) {
+                continue;
+            }
+
+            let (start_block, end_block) = (start.block_number.as_u64(), end.block_number.as_u64());
+            if end_block == start_block + 1 {
+                changes.push(end);
+                continue;
+            }
+
+            let middle = self
+                .get(start_block + (end_block - start_block) / 2)
+                .await?;
+            intervals.push((middle.clone(), end));
+            intervals.push((start, middle));
+        }
+
+        Ok(())
+    }
+
+    // The next sample and the changes found up to it, in block order, so they can be written
+    // out as the search goes. The first call returns the value at `origin_block`. Returns
+    // None once `terminal_block` is reached or we got interrupted. An interval we got
+    // interrupted in is dropped as a whole, as part of its changes could be missing.
+    pub async fn next(
+        &mut self,
+        interrupted: &AtomicBool,
+    ) -> Result<Option<(u64, Vec<StateChange>)>, Box<dyn std::error::Error>> {
+        let last = match self.last.take() {
+            Some(last) => last,
+            None => {
+                let origin_block = match self.samples.pop_front() {
+                    Some(origin_block) => origin_block,
+                    None => return Ok(None),
+                };
+                let first = self.get(origin_block).await?;
+                self.last = Some(first.clone());
+                return Ok(Some((origin_block, vec![first])));
+            }
+        };
+
+        let block = match self.samples.pop_front() {
+            Some(block) => block,
+            None => {
+                self.report();
+                return Ok(None);
+            }
+        };
+        if interrupted.load(Ordering::SeqCst) {
+            println!("!!! \x1b[93mWARNING:\x1b[0m Interrupted, changes after block {} were not searched !!!", last.block_number);
+            self.report();
+            return Ok(None);
+        }
+
+        let next = self.get(block).await?;
+        let mut points = Vec::new();
+
+        // Bisecting only finds changes between ends that differ, which misses values
+        // that changed back within the interval. So check the value after every block
+        // the contract was called in, whether the ends differ or not.
+        let start_block = last.block_number.as_u64();
+        if self.confirm_with_traces && block > start_block + 1 {
+            for touched in self.touched_blocks(start_block + 1, block - 1).await? {
+                self.confirm_queries += 1;
+                points.push(self.get(touched).await?);
+            }
+        }
+        points.push(next.clone());
+
+        let mut changes = Vec::new();
+        let mut start = last;
+        for point in points {
+            self.bisect(start, point.clone(), &mut changes, interrupted)
+                .await?;
+            start = point;
+        }
+        if interrupted.load(Ordering::SeqCst) {
+            println!("!!! \x1b[93mWARNING:\x1b[0m Interrupted, changes after block {} were not searched !!!", start_block);
+            self.report();
+            return Ok(None);
+        }
+        changes.sort_by_key(|change| change.block_number);
+
+        println!(
+            "Searched until block {}, found {} changes with {} queries",
+            block,
+            changes.len(),
+            self.queries
+        );
+        self.last = Some(next);
+
+        Ok(Some((block, changes)))
+    }
+
+    fn report(&self) {
+        println!(
+            "Bisect made {} storage queries and {} trace_filter calls, a full scan would have made {}",
+            self.queries, self.trace_queries, self.scanned
+        );
+        if self.confirm_with_traces {
+            println!(
+                "{} of the storage queries checked blocks the contract was called in",
+                self.confirm_queries
+            );
+        }
+    }
+}
@@ -1,9 +1,17 @@
-use crate::rpc::format::{
-    decimal_to_hex,
-    hex_to_decimal,
-};
+use crate::rpc::format::hex_to_decimal;
 use crate::rpc::types::CallParams;
-use crate::tracker::common::set_filename_and_serialize;
+use crate::tracker::annotate::Annotator;
+use crate::tracker::common::{
+    output_filename,
+    record_change,
+};
+use crate::tracker::decoder::StateDecoder;
+use crate::tracker::fetch::{
+    Query,
+    RangeFetcher,
+};
+use crate::tracker::format::NumberFormat;
+use crate::tracker::output::ChangeWriter;
 use crate::tracker::types::*;
 use crate::RpcConnection;
 use serde_json::Value;
@@ -16,16 +24,22 @@ use std::sync::Arc;
 
 use ctrlc;
 
+#[allow(clippy::too_many_arguments)]
 pub async fn call_track(
     source_rpc: RpcConnection,
     calldata: String,
+    decoder: StateDecoder,
     contract_address: String,
     terminal_block: Option<u64>,
     origin_block: u64,
     query_interval: Option<u64>,
-    decimal: bool,
+    concurrency: usize,
+    chunk_size: u64,
+    number_format: NumberFormat,
     path: String,
     filename: String,
+    resume: bool,
+    annotate: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let interrupted = Arc::new(AtomicBool::new(false));
     let interrupted_clone = interrupted.clone();
@@ -35,9 +49,12 @@ pub async fn call_track(
     let mut interval = 1;
 
     // Print warning that sothis does not have the full context
-    if query_interval.is_some() {
+    if let Some(query_interval) = query_interval {
         println!("!!! \x1b[93mWARNING:\x1b[0m Query interval is set, sothis will not have the full context of the eth_calls !!!");
-        interval = query_interval.unwrap();
+        if annotate && query_interval > 1 {
+            println!("!!! \x1b[93mWARNING:\x1b[0m --annotate only looks at the block a change was found at, transactions in the blocks skipped before it are missed !!!");
+        }
+        interval = query_interval;
     }
 
     ctrlc::set_handler(move || {
@@ -67,49 +84,69 @@ pub async fn call_track(
         return Err("Origin block cannot be higher than the terminal block".into());
     }
 
-    let mut current_block = origin_block;
-    while current_block < terminal_block {
-        if interrupted.load(Ordering::SeqCst) {
-            break;
-        }
+    let query = Query::Call(CallParams {
+        from: Value::Null,
+        to: contract_address.clone(),
+        data: calldata.clone(),
+    });
+
+    let filename = output_filename(filename, &contract_address, "calldata", calldata);
+    let mut writer = ChangeWriter::open(
+        &path,
+        &filename,
+        &mut storage,
+        resume,
+        number_format,
+        decoder.csv_columns(annotate),
+        |_, change| decoder.state_change(change.block_number, change.value),
+    )?;
 
-        let tx = CallParams {
-            from: Value::Null,
-            to: contract_address.clone(),
-            data: calldata.clone(),
-        };
-
-        let latest_call = source_rpc.call(tx, decimal_to_hex(current_block)).await?;
-        let slot = StateChange {
-            block_number: current_block.into(),
-            value: latest_call,
-        };
-
-        if storage
-            .state_changes
-            .last()
-            .map(|change| change.value != slot.value)
-            .unwrap_or(true)
-        {
-            println!(
-                "New call value at block {}: {:?}",
-                slot.block_number, &slot.value
-            );
-            storage.state_changes.push(slot);
-        }
+    // Continue after the last block the run we're resuming scanned, or its last change
+    let mut origin_block = origin_block;
+    if let Some(last) = writer.resumed_until().or(storage
+        .state_changes
+        .last()
+        .map(|change| change.block_number.as_u64()))
+    {
+        origin_block = origin_block.max(last + interval);
+        println!("Resuming from block {}", origin_block);
+    }
 
-        current_block += interval;
+    // Changes found before an error are still written out
+    let result: Result<(), Box<dyn std::error::Error>> = async {
+        let mut annotator = annotate.then(|| Annotator::new(&source_rpc));
+        let mut fetcher = RangeFetcher::new(
+            &source_rpc,
+            &query,
+            &contract_address,
+            origin_block,
+            terminal_block,
+            interval,
+            concurrency,
+            chunk_size,
+            &interrupted,
+        );
+
+        while let Some((block, value)) = fetcher.next().await? {
+            let slot = decoder.state_change(block.into(), TrackedValue::parse(&value))?;
+            record_change(
+                slot,
+                &mut storage.state_changes,
+                &decoder,
+                &mut annotator,
+                (&contract_address, &query),
+                "call value",
+                |change| writer.append(None, change),
+            )
+            .await?;
+            writer.scanned(block)?;
+        }
+        Ok(())
     }
+    .await;
 
-    set_filename_and_serialize(
-        path,
-        filename,
-        storage,
-        contract_address,
-        "calldata",
-        calldata,
-        decimal,
-    )?;
+    writer.finish(&storage)?;
+    result?;
 
     Ok(())
 }
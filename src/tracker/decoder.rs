@@ -0,0 +1,322 @@
+use crate::tracker::layout::{
+    decode_word,
+    StorageVariable,
+};
+use crate::tracker::signature::CallSignature;
+use crate::tracker::types::*;
+
+use ethers::types::U256;
+
+// Decodes tracked slot values and call results, and decides what counts as a change.
+#[derive(Debug, Clone, Default)]
+pub struct StateDecoder {
+    pub variable: Option<StorageVariable>,
+    pub signature: Option<CallSignature>,
+    pub fields: Vec<SlotField>,
+    // Only changes to these fields count, all of them if empty
+    pub watch: Vec<String>,
+}
+
+impl StateDecoder {
+    pub fn new(
+        variable: Option<StorageVariable>,
+        signature: Option<CallSignature>,
+        fields: Vec<SlotField>,
+        watch: Vec<String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if signature.is_some() && !fields.is_empty() {
+            return Err("Fields can only be decoded from storage slots, not calls".into());
+        }
+        for field in &fields {
+            if field.size == 0 || field.offset + field.size > 32 {
+                return Err(format!(
+                    "Field {} with {} bytes at offset {} doesn't fit in a slot",
+                    field.name, field.size, field.offset
+                )
+                .into());
+            }
+            let known_type = ["uint", "int", "bool", "address", "bytes"]
+                .iter()
+                .any(|prefix| field.field_type.starts_with(prefix));
+            if !known_type {
+                return Err(format!(
+                    "Field {} has unsupported type {}",
+                    field.name, field.field_type
+                )
+                .into());
+            }
+            if fields
+                .iter()
+                .filter(|other| other.name == field.name)
+                .count()
+                > 1
+            {
+                return Err(format!("Field name {} is used more than once", field.name).into());
+            }
+        }
+        let outputs = signature.iter().flat_map(|signature| &signature.outputs);
+        let names: Vec<&String> = fields
+            .iter()
+            .map(|field| &field.name)
+            .chain(outputs.map(|(name, _)| name))
+            .collect();
+        for name in &watch {
+            if !names.contains(&name) {
+                return Err(format!("Watched field {} is not declared", name).into());
+            }
+        }
+
+        Ok(Self {
+            variable,
+            signature,
+            fields,
+            watch,
+        })
+    }
+
+    // Parse `--fields` and `--watch_fields`. Fields are comma separated `name:offset:size:type`,
+    // e.g. `sqrtPriceX96:0:20:uint160,tick:20:3:int24`.
+    pub fn from_args(
+        variable: Option<StorageVariable>,
+        signature: Option<CallSignature>,
+        fields: Option<&String>,
+        watch: Option<&String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let fields = fields
+            .map(|fields| fields.split(',').map(parse_field).collect())
+            .transpose()?
+            .unwrap_or_default();
+        let watch = watch
+            .map(|watch| {
+                watch
+                    .split(',')
+                    .map(|name| name.trim().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self::new(variable, signature, fields, watch)
+    }
+
+    // The CSV columns of the changes we decode, in the order `StateChange::serialize_csv`
+    // writes them.
+    pub fn csv_columns(&self, annotated: bool) -> CsvColumns {
+        let outputs = self
+            .signature
+            .iter()
+            .flat_map(|signature| &signature.outputs);
+        CsvColumns {
+            decoded: self
+                .variable
+                .iter()
+                .map(|_| "decoded".to_string())
+                .chain(self.fields.iter().map(|field| field.name.clone()))
+                .chain(outputs.map(|(name, _)| name.clone()))
+                .collect(),
+            annotated,
+        }
+    }
+
+    pub fn state_change(
+        &self,
+        block_number: U256,
+        value: TrackedValue,
+    ) -> Result<StateChange, Box<dyn std::error::Error>> {
+        let hex = value.hex();
+        let decoded = self
+            .variable
+            .as_ref()
+            .map(|variable| variable.decode(&hex))
+            .transpose()?;
+
+        let fields = match (&self.signature, self.fields.is_empty()) {
+            // Calls return `0x` before the contract is deployed or when they revert, keep
+            // the raw value without fields then, so it still shows up as a change
+            (Some(signature), _) => signature.decode(&hex).ok(),
+            (None, true) => None,
+            (None, false) => {
+                let mut fields = Vec::new();
+                for field in &self.fields {
+                    fields.push((
+                        field.name.clone(),
+                        decode_word(&hex, field.offset, field.size, &field.field_type)?,
+                    ));
+                }
+                Some(DecodedFields(fields))
+            }
+        };
+
+        Ok(StateChange {
+            block_number,
+            value,
+            decoded,
+            fields,
+            timestamp: None,
+            transactions: None,
+        })
+    }
+
+    // Whether `change` is a change from `last`. Decoded values are compared when we
+    // have them, so changes to other variables or fields packed in the same slot
+    // are ignored.
+    pub fn differs(&self, last: &StateChange, change: &StateChange) -> bool {
+        if let (Some(last_fields), Some(fields)) = (&last.fields, &change.fields) {
+            let watched = |name: &String| self.watch.is_empty() || self.watch.contains(name);
+            return fields
+                .0
+                .iter()
+                .filter(|(name, _)| watched(name))
+                .any(|(name, value)| last_fields.get(name) != Some(value));
+        }
+
+        match (&last.decoded, &change.decoded) {
+            (Some(a), Some(b)) => a != b,
+            _ => last.value != change.value,
+        }
+    }
+
+    // What we print when a change is found.
+    pub fn describe(&self, change: &StateChange) -> String {
+        match (&change.fields, &change.decoded) {
+            (Some(fields), _) => fields.to_string(),
+            (None, Some(decoded)) => format!("{:?}", decoded),
+            (None, None) if self.signature.is_some() => {
+                format!(
+                    "{:?} (could not decode the return values)",
+                    change.value.hex()
+                )
+            }
+            (None, None) => format!("{:?}", change.value.hex()),
+        }
+    }
+}
+
+fn parse_field(field: &str) -> Result<SlotField, Box<dyn std::error::Error>> {
+    let parts: Vec<&str> = field.split(':').map(str::trim).collect();
+    match parts[..] {
+        [name, offset, size, field_type] => {
+            Ok(SlotField {
+                name: name.to_string(),
+                offset: offset.parse()?,
+                size: size.parse()?,
+                field_type: field_type.to_string(),
+            })
+        }
+        _ => Err(format!("Invalid field {}, expected name:offset:size:type", field).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::format::NumberFormat;
+
+    // Uniswap V3 `slot0` with sqrtPriceX96 = 2^96, tick = -1, observationIndex = 7
+    const SLOT0: &str = "0x000000000000000007ffffff0000000000000001000000000000000000000000";
+
+    fn slot0_decoder(watch: Option<&str>) -> StateDecoder {
+        StateDecoder::from_args(
+            None,
+            None,
+            Some(
+                &"sqrtPriceX96:0:20:uint160,tick:20:3:int24,observationIndex:23:2:uint16"
+                    .to_string(),
+            ),
+            watch.map(str::to_string).as_ref(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn decodes_packed_fields() {
+        let change = slot0_decoder(None)
+            .state_change(1.into(), TrackedValue::parse(SLOT0))
+            .unwrap();
+        let fields = change.fields.unwrap();
+
+        assert_eq!(
+            fields.get("sqrtPriceX96").unwrap(),
+            &FieldValue::Uint(U256::one() << 96)
+        );
+        assert_eq!(fields.get("tick").unwrap(), &FieldValue::Int((-1).into()));
+        assert_eq!(
+            fields.get("observationIndex").unwrap(),
+            &FieldValue::Uint(7.into())
+        );
+    }
+
+    #[test]
+    fn only_watched_fields_count_as_changes() {
+        let decoder = slot0_decoder(Some("tick"));
+        let last = decoder
+            .state_change(1.into(), TrackedValue::parse(SLOT0))
+            .unwrap();
+
+        // Only the observation index moved
+        let index_moved = SLOT0.replace("0007", "0008");
+        let change = decoder
+            .state_change(2.into(), TrackedValue::parse(&index_moved))
+            .unwrap();
+        assert!(!decoder.differs(&last, &change));
+        assert!(slot0_decoder(None).differs(&last, &change));
+
+        let tick_moved = SLOT0.replace("ffffff", "fffffe");
+        let change = decoder
+            .state_change(3.into(), TrackedValue::parse(&tick_moved))
+            .unwrap();
+        assert!(decoder.differs(&last, &change));
+    }
+
+    #[test]
+    fn decodes_call_results() {
+        let signature =
+            CallSignature::parse("latestRoundData()(uint80 roundId,int256 answer)").unwrap();
+        let decoder = StateDecoder::new(None, Some(signature), Vec::new(), Vec::new()).unwrap();
+
+        let value = format!("0x{:064x}{}", 5, "f".repeat(63) + "b");
+        let fields = decoder
+            .state_change(1.into(), TrackedValue::parse(&value))
+            .unwrap()
+            .fields
+            .unwrap();
+        assert_eq!(fields.get("roundId").unwrap(), &FieldValue::Uint(5.into()));
+        assert_eq!(fields.get("answer").unwrap(), &FieldValue::Int((-5).into()));
+
+        // Numbers in return values are formatted like the value of a slot
+        let fixed = NumberFormat::Fixed {
+            decimals: 8,
+            signed: false,
+        };
+        assert_eq!(fixed.field(fields.get("answer").unwrap()), "-0.00000005");
+
+        // Nothing to decode before the contract exists
+        let last = decoder
+            .state_change(1.into(), TrackedValue::parse("0x"))
+            .unwrap();
+        assert_eq!(last.fields, None);
+        let change = decoder
+            .state_change(2.into(), TrackedValue::parse(&format!("0x{:0128x}", 1)))
+            .unwrap();
+        assert!(decoder.differs(&last, &change));
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        let invalid = |fields: &str, watch: Option<&str>| {
+            StateDecoder::from_args(
+                None,
+                None,
+                Some(&fields.to_string()),
+                watch.map(str::to_string).as_ref(),
+            )
+            .is_err()
+        };
+
+        assert!(invalid("a:30:3:uint24", None));
+        assert!(invalid("a:0:0:uint8", None));
+        assert!(invalid("a:0:1:string", None));
+        assert!(invalid("a:0:1:uint8,a:1:1:uint8", None));
+        assert!(invalid("a:0:1", None));
+        assert!(invalid("a:0:1:uint8", Some("b")));
+    }
+}
@@ -1,8 +1,17 @@
-use crate::rpc::format::{
-    decimal_to_hex,
-    hex_to_decimal,
+use crate::rpc::format::hex_to_decimal;
+use crate::tracker::annotate::Annotator;
+use crate::tracker::bisect::Bisector;
+use crate::tracker::common::{
+    output_filename,
+    record_change,
 };
-use crate::tracker::common::set_filename_and_serialize;
+use crate::tracker::decoder::StateDecoder;
+use crate::tracker::fetch::{
+    Query,
+    RangeFetcher,
+};
+use crate::tracker::format::NumberFormat;
+use crate::tracker::output::ChangeWriter;
 use crate::tracker::types::*;
 use crate::RpcConnection;
 
@@ -16,16 +25,23 @@ use ctrlc;
 use ethers::types::U256;
 
 // We querry historical storage from a node instead of waiting for new blocks.
+#[allow(clippy::too_many_arguments)]
 pub async fn fast_track_state(
     source_rpc: RpcConnection,
     storage_slot: U256,
+    decoder: StateDecoder,
+    search: SearchStrategy,
     contract_address: String,
     terminal_block: Option<u64>,
     origin_block: u64,
     query_interval: Option<u64>,
-    decimal: bool,
+    concurrency: usize,
+    chunk_size: u64,
+    number_format: NumberFormat,
     path: String,
     filename: String,
+    resume: bool,
+    annotate: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let interrupted = Arc::new(AtomicBool::new(false));
     let interrupted_clone = interrupted.clone();
@@ -35,9 +51,12 @@ pub async fn fast_track_state(
     let mut interval = 1;
 
     // Print warning that sothis does not have the full context
-    if query_interval.is_some() {
+    if let Some(query_interval) = query_interval {
         println!("!!! \x1b[93mWARNING:\x1b[0m Query interval is set, sothis will not have the full context of the storage slot changes !!!");
-        interval = query_interval.unwrap();
+        if annotate && query_interval > 1 {
+            println!("!!! \x1b[93mWARNING:\x1b[0m --annotate only looks at the block a change was found at, transactions in the blocks skipped before it are missed !!!");
+        }
+        interval = query_interval;
     }
 
     ctrlc::set_handler(move || {
@@ -67,49 +86,111 @@ pub async fn fast_track_state(
         return Err("Origin block cannot be higher than the terminal block".into());
     }
 
-    let mut current_block = origin_block;
-    while current_block < terminal_block {
-        if interrupted.load(Ordering::SeqCst) {
-            break;
-        }
+    let filename = output_filename(
+        filename,
+        &contract_address,
+        "slot",
+        storage_slot.to_string(),
+    );
+    let mut writer = ChangeWriter::open(
+        &path,
+        &filename,
+        &mut storage,
+        resume,
+        number_format,
+        decoder.csv_columns(annotate),
+        |_, change| decoder.state_change(change.block_number, change.value),
+    )?;
 
-        let latest_slot = source_rpc
-            .get_storage_at_block(
-                contract_address.clone(),
-                storage_slot,
-                decimal_to_hex(current_block),
-            )
-            .await?;
-        let slot = StateChange {
-            block_number: current_block.into(),
-            value: latest_slot,
-        };
-
-        if storage
-            .state_changes
-            .last()
-            .map(|change| change.value != slot.value)
-            .unwrap_or(true)
-        {
-            println!(
-                "New storage slot value at block {}: {:?}",
-                slot.block_number, &slot.value
-            );
-            storage.state_changes.push(slot);
+    // Continue after the last block the run we're resuming scanned, or its last change
+    let mut origin_block = origin_block;
+    if let Some(last) = writer.resumed_until().or(storage
+        .state_changes
+        .last()
+        .map(|change| change.block_number.as_u64()))
+    {
+        origin_block = origin_block.max(last + interval);
+        println!("Resuming from block {}", origin_block);
+    }
+
+    // Changes found before an error are still written out
+    let result: Result<(), Box<dyn std::error::Error>> = async {
+        if origin_block >= terminal_block {
+            println!("Already tracked until the terminal block");
+            return Ok(());
         }
 
-        current_block += interval;
+        let mut annotator = annotate.then(|| Annotator::new(&source_rpc));
+        let query = Query::Slot(storage_slot);
+
+        match search {
+            SearchStrategy::Bisect {
+                sample_interval,
+                confirm_with_traces,
+            } => {
+                let mut bisector = Bisector::new(
+                    &source_rpc,
+                    &contract_address,
+                    storage_slot,
+                    &decoder,
+                    origin_block,
+                    terminal_block,
+                    sample_interval,
+                    confirm_with_traces,
+                );
+
+                // Write the changes of every sampled interval as soon as it's searched
+                while let Some((block, changes)) = bisector.next(&interrupted).await? {
+                    for slot in changes {
+                        record_change(
+                            slot,
+                            &mut storage.state_changes,
+                            &decoder,
+                            &mut annotator,
+                            (&contract_address, &query),
+                            "storage slot value",
+                            |change| writer.append(None, change),
+                        )
+                        .await?;
+                    }
+                    writer.scanned(block)?;
+                }
+            }
+            SearchStrategy::Scan => {
+                let mut fetcher = RangeFetcher::new(
+                    &source_rpc,
+                    &query,
+                    &contract_address,
+                    origin_block,
+                    terminal_block,
+                    interval,
+                    concurrency,
+                    chunk_size,
+                    &interrupted,
+                );
+
+                while let Some((block, value)) = fetcher.next().await? {
+                    let slot = decoder.state_change(block.into(), TrackedValue::parse(&value))?;
+                    record_change(
+                        slot,
+                        &mut storage.state_changes,
+                        &decoder,
+                        &mut annotator,
+                        (&contract_address, &query),
+                        "storage slot value",
+                        |change| writer.append(None, change),
+                    )
+                    .await?;
+                    writer.scanned(block)?;
+                }
+            }
+        }
+        Ok(())
     }
+    .await;
 
-    set_filename_and_serialize(
-        path,
-        filename,
-        storage,
-        contract_address,
-        "slot",
-        storage_slot.to_string(),
-        decimal,
-    )?;
+    writer.finish(&storage)?;
+    result?;
 
     Ok(())
 }
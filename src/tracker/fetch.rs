@@ -0,0 +1,346 @@
+use crate::rpc::error::RequestError;
+use crate::rpc::format::decimal_to_hex;
+use crate::rpc::types::CallParams;
+use crate::RpcConnection;
+use serde_json::{
+    json,
+    Value,
+};
+
+use std::collections::VecDeque;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::sync::Arc;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use ethers::types::U256;
+use tokio::task::JoinHandle;
+
+// The values of every target at each block of a chunk, fetched in the background.
+type ChunkHandle = JoinHandle<Result<Vec<(u64, Vec<TargetResult>)>, String>>;
+
+// The value of a target at a block, or why we couldn't get it.
+pub type TargetResult = Result<String, String>;
+
+// How often we report progress while fetching a range.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+// How many times a failed query is retried, waiting twice as long each time.
+const MAX_FETCH_RETRIES: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// A storage slot or call of a contract, queried at historic blocks.
+#[derive(Debug, Clone)]
+pub enum Query {
+    Slot(U256),
+    Call(CallParams),
+}
+
+impl Query {
+    pub fn request(&self, address: &str, block: &str) -> (&'static str, Value) {
+        match self {
+            Query::Slot(slot) => ("eth_getStorageAt", json!([address, slot, block])),
+            Query::Call(tx) => ("eth_call", json!([tx, block])),
+        }
+    }
+
+    pub async fn fetch(
+        &self,
+        source_rpc: &RpcConnection,
+        address: &str,
+        block: &str,
+    ) -> Result<String, RequestError> {
+        match self {
+            Query::Slot(slot) => {
+                source_rpc
+                    .get_storage_at_block(address.to_string(), *slot, block.to_string())
+                    .await
+            }
+            Query::Call(tx) => source_rpc.call(tx.clone(), block.to_string()).await,
+        }
+    }
+
+    // `fetch`, retrying with backoff so a flaky node or a rate limit doesn't end the scan.
+    async fn fetch_with_retries(
+        &self,
+        source_rpc: &RpcConnection,
+        address: &str,
+        block: &str,
+    ) -> Result<String, RequestError> {
+        let mut delay = RETRY_DELAY;
+        for _ in 0..MAX_FETCH_RETRIES {
+            match self.fetch(source_rpc, address, block).await {
+                Err(e) if !is_revert(&e) => (),
+                result => return result,
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+        self.fetch(source_rpc, address, block).await
+    }
+}
+
+// A reverted call reverts again, there's no point in retrying it.
+fn is_revert(error: &RequestError) -> bool {
+    match error {
+        RequestError::RequestFailed(message) => message.to_lowercase().contains("revert"),
+        _ => false,
+    }
+}
+
+// Fetches the value of one or more targets at every `interval`-th block of
+// `origin_block..terminal_block`.
+//
+// The range is split into chunks of `chunk_size` blocks, and up to `concurrency` chunks
+// are fetched at the same time. Values are returned in block order by `next`, so the
+// caller can compare each value with the previous one like in a sequential scan.
+// All targets of a block are fetched in a single JSON-RPC batch, or one by one if the
+// node doesn't support batching.
+pub struct RangeFetcher<'a> {
+    source_rpc: &'a RpcConnection,
+    // Address and query of every target
+    targets: Arc<Vec<(String, Query)>>,
+    batching: Arc<AtomicBool>,
+    // Stop at the first failed query, as there is nothing left to scan when the only target fails
+    stop_on_error: bool,
+    concurrency: usize,
+    chunks: VecDeque<Vec<u64>>,
+    in_flight: VecDeque<ChunkHandle>,
+    results: VecDeque<(u64, Vec<TargetResult>)>,
+    interrupted: &'a AtomicBool,
+    total: usize,
+    fetched: usize,
+    start: Instant,
+    last_report: Instant,
+}
+
+impl<'a> RangeFetcher<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        source_rpc: &'a RpcConnection,
+        query: &Query,
+        address: &str,
+        origin_block: u64,
+        terminal_block: u64,
+        interval: u64,
+        concurrency: usize,
+        chunk_size: u64,
+        interrupted: &'a AtomicBool,
+    ) -> Self {
+        let mut fetcher = Self::with_targets(
+            source_rpc,
+            vec![(address.to_string(), query.clone())],
+            origin_block,
+            terminal_block,
+            interval,
+            concurrency,
+            chunk_size,
+            interrupted,
+        );
+        fetcher.stop_on_error = true;
+        fetcher
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_targets(
+        source_rpc: &'a RpcConnection,
+        targets: Vec<(String, Query)>,
+        origin_block: u64,
+        terminal_block: u64,
+        interval: u64,
+        concurrency: usize,
+        chunk_size: u64,
+        interrupted: &'a AtomicBool,
+    ) -> Self {
+        let blocks: Vec<u64> = (origin_block..terminal_block)
+            .step_by(interval.max(1) as usize)
+            .collect();
+
+        Self {
+            source_rpc,
+            targets: Arc::new(targets),
+            batching: Arc::new(AtomicBool::new(true)),
+            stop_on_error: false,
+            concurrency: concurrency.max(1),
+            chunks: blocks
+                .chunks(chunk_size.max(1) as usize)
+                .map(|chunk| chunk.to_vec())
+                .collect(),
+            in_flight: VecDeque::new(),
+            results: VecDeque::new(),
+            interrupted,
+            total: blocks.len(),
+            fetched: 0,
+            start: Instant::now(),
+            last_report: Instant::now(),
+        }
+    }
+
+    // The next block and the value of the only target, or None once the range is done
+    // or we got interrupted.
+    pub async fn next(&mut self) -> Result<Option<(u64, String)>, Box<dyn std::error::Error>> {
+        match self.next_block().await? {
+            Some((block, mut results)) => Ok(Some((block, results.remove(0)?))),
+            None => Ok(None),
+        }
+    }
+
+    // The next block and the result of every target, in the order of the targets.
+    pub async fn next_block(
+        &mut self,
+    ) -> Result<Option<(u64, Vec<TargetResult>)>, Box<dyn std::error::Error>> {
+        while self.results.is_empty() {
+            if self.interrupted.load(Ordering::SeqCst) {
+                self.abort();
+                return Ok(None);
+            }
+
+            // Keep the pool full
+            while self.in_flight.len() < self.concurrency {
+                let chunk = match self.chunks.pop_front() {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+
+                let (source_rpc, targets, batching, stop_on_error) = (
+                    self.source_rpc.clone(),
+                    self.targets.clone(),
+                    self.batching.clone(),
+                    self.stop_on_error,
+                );
+                self.in_flight.push_back(tokio::spawn(async move {
+                    let mut results = Vec::new();
+                    for block in chunk {
+                        let values = fetch_block(&source_rpc, &targets, block, &batching).await;
+                        if stop_on_error {
+                            if let Some(Err(e)) = values.iter().find(|value| value.is_err()) {
+                                return Err(e.clone());
+                            }
+                        }
+                        results.push((block, values));
+                    }
+                    Ok(results)
+                }));
+            }
+
+            // Chunks are awaited in the order they were spawned, which keeps results in block order
+            let handle = match self.in_flight.pop_front() {
+                Some(handle) => handle,
+                None => return Ok(None),
+            };
+            let chunk = match handle.await {
+                Ok(Ok(chunk)) => chunk,
+                // Don't leave the other chunks querying the node in the background
+                Ok(Err(e)) => {
+                    self.abort();
+                    return Err(e.into());
+                }
+                Err(e) => {
+                    self.abort();
+                    return Err(e.into());
+                }
+            };
+            self.results.extend(chunk);
+            self.fetched += self.results.len();
+
+            if self.last_report.elapsed() >= PROGRESS_INTERVAL || self.fetched == self.total {
+                report_progress(self.fetched, self.total, self.start.elapsed());
+                self.last_report = Instant::now();
+            }
+        }
+
+        Ok(self.results.pop_front())
+    }
+
+    fn abort(&mut self) {
+        self.chunks.clear();
+        for handle in self.in_flight.drain(..) {
+            handle.abort();
+        }
+    }
+}
+
+// Also stop fetching when the caller gives up on the range early.
+impl Drop for RangeFetcher<'_> {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+
+// Get the value of every target at `block`, batched unless the node turned out not to
+// support it. Queries that fail are retried one by one.
+async fn fetch_block(
+    source_rpc: &RpcConnection,
+    targets: &[(String, Query)],
+    block: u64,
+    batching: &AtomicBool,
+) -> Vec<TargetResult> {
+    let hex_block = decimal_to_hex(block);
+    let failed = |e: RequestError| format!("Failed to query block {}: {}", block, e);
+    let mut batched: Vec<Option<TargetResult>> = targets.iter().map(|_| None).collect();
+
+    if targets.len() > 1 && batching.load(Ordering::SeqCst) {
+        let requests = targets
+            .iter()
+            .map(|(address, query)| query.request(address, &hex_block))
+            .collect();
+        match source_rpc.send_batch(requests).await {
+            Ok(results) => {
+                for (slot, result) in batched.iter_mut().zip(results) {
+                    match result {
+                        Ok(value) => *slot = Some(Ok(value.trim_matches('"').to_string())),
+                        Err(e) if is_revert(&e) => *slot = Some(Err(failed(e))),
+                        Err(_) => (),
+                    }
+                }
+            }
+            Err(e) => {
+                if batching.swap(false, Ordering::SeqCst) {
+                    println!("!!! \x1b[93mWARNING:\x1b[0m Batch request failed, querying targets one by one: {} !!!", e);
+                }
+            }
+        }
+    }
+
+    let mut results = Vec::new();
+    for ((address, query), result) in targets.iter().zip(batched) {
+        let result = match result {
+            Some(result) => result,
+            None => {
+                query
+                    .fetch_with_retries(source_rpc, address, &hex_block)
+                    .await
+                    .map_err(failed)
+            }
+        };
+        results.push(result);
+    }
+    results
+}
+
+fn report_progress(fetched: usize, total: usize, elapsed: Duration) {
+    let rate = fetched as f64 / elapsed.as_secs_f64().max(0.001);
+    let eta = Duration::from_secs_f64((total - fetched) as f64 / rate.max(0.001));
+
+    println!(
+        "Fetched {}/{} blocks, {:.1} blocks/s, ETA {}",
+        fetched,
+        total,
+        rate,
+        format_duration(eta)
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
@@ -0,0 +1,163 @@
+use crate::tracker::types::{
+    FieldValue,
+    TrackedValue,
+};
+
+use ethers::types::{
+    I256,
+    U256,
+};
+
+// How tracked numbers are written to the output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberFormat {
+    // As the node returned them
+    Hex,
+    Decimal,
+    // Two's complement, for `int` slots and return values
+    Signed,
+    // Divided by 10^decimals, e.g. 18 for most ERC20 balances or 8 for Chainlink answers
+    Fixed { decimals: u32, signed: bool },
+}
+
+impl NumberFormat {
+    // Parse `hex`, `decimal`, `signed`, `fixed:{decimals}` or `signed_fixed:{decimals}`.
+    pub fn parse(format: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let invalid = || {
+            format!(
+                "Invalid number format {}, use hex, decimal, signed, fixed:{{decimals}} or signed_fixed:{{decimals}}",
+                format
+            )
+        };
+        let fixed = |decimals: &str, signed: bool| -> Result<Self, Box<dyn std::error::Error>> {
+            let decimals: u32 = decimals.parse().map_err(|_| invalid())?;
+            // 10^78 doesn't fit in a U256
+            if decimals > 77 {
+                return Err(
+                    format!("Can't format with {} decimals, the most is 77", decimals).into(),
+                );
+            }
+            Ok(NumberFormat::Fixed { decimals, signed })
+        };
+
+        match format.split_once(':') {
+            None => {
+                match format {
+                    "hex" => Ok(NumberFormat::Hex),
+                    "decimal" => Ok(NumberFormat::Decimal),
+                    "signed" => Ok(NumberFormat::Signed),
+                    _ => Err(invalid().into()),
+                }
+            }
+            Some(("fixed", decimals)) => fixed(decimals, false),
+            Some(("signed_fixed", decimals)) => fixed(decimals, true),
+            Some(_) => Err(invalid().into()),
+        }
+    }
+
+    // Format a block number, timestamp or storage slot. These are always whole and unsigned,
+    // so they're only either hex or decimal.
+    pub fn integer(&self, number: U256) -> String {
+        match self {
+            NumberFormat::Hex => format!("{:#x}", number),
+            _ => number.to_string(),
+        }
+    }
+
+    // Format a tracked value. Longer call results than a word aren't numbers, they're kept
+    // as the node returned them.
+    pub fn value(&self, value: &TrackedValue) -> String {
+        let number = match value {
+            TrackedValue::Word(number) => *number,
+            TrackedValue::Raw(value) => return value.clone(),
+        };
+
+        match *self {
+            NumberFormat::Hex => value.hex(),
+            NumberFormat::Decimal => number.to_string(),
+            NumberFormat::Signed => I256::from_raw(number).to_string(),
+            NumberFormat::Fixed { decimals, signed } => {
+                match signed {
+                    true => signed_fixed_point(I256::from_raw(number), decimals),
+                    false => fixed_point(number, decimals),
+                }
+            }
+        }
+    }
+
+    // Format a decoded field. Its type says whether it's signed, and it's decimal unless
+    // it's written as a fixed point number.
+    pub fn field(&self, value: &FieldValue) -> String {
+        match (*self, value) {
+            (NumberFormat::Fixed { decimals, .. }, FieldValue::Uint(number)) => {
+                fixed_point(*number, decimals)
+            }
+            (NumberFormat::Fixed { decimals, .. }, FieldValue::Int(number)) => {
+                signed_fixed_point(*number, decimals)
+            }
+            _ => value.to_string(),
+        }
+    }
+}
+
+// `number / 10^decimals` without trailing zeros, e.g. `1.5` for 15 with one decimal.
+fn fixed_point(number: U256, decimals: u32) -> String {
+    let scale = U256::exp10(decimals as usize);
+    let (whole, fraction) = number.div_mod(scale);
+    if fraction.is_zero() {
+        return whole.to_string();
+    }
+
+    let fraction = format!(
+        "{:0>width$}",
+        fraction.to_string(),
+        width = decimals as usize
+    );
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+fn signed_fixed_point(number: I256, decimals: u32) -> String {
+    let sign = if number.is_negative() { "-" } else { "" };
+    format!("{}{}", sign, fixed_point(number.unsigned_abs(), decimals))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_words_and_fields() {
+        let answer = TrackedValue::parse(&format!("0x{}", "f".repeat(63) + "b"));
+        assert_eq!(NumberFormat::Signed.value(&answer), "-5");
+        assert_eq!(
+            NumberFormat::parse("signed_fixed:2")
+                .unwrap()
+                .value(&answer),
+            "-0.05"
+        );
+        assert_eq!(
+            NumberFormat::Hex.value(&answer),
+            format!("0x{}", "f".repeat(63) + "b")
+        );
+
+        // More than a word isn't a number
+        let result = TrackedValue::parse(&format!("0x{:0128x}", 1));
+        assert_eq!(
+            NumberFormat::Decimal.value(&result),
+            format!("0x{:0128x}", 1)
+        );
+
+        // Fields are signed by their type, not the format
+        let fixed = NumberFormat::parse("fixed:8").unwrap();
+        assert_eq!(
+            fixed.field(&FieldValue::Uint(150000000000u64.into())),
+            "1500"
+        );
+        assert_eq!(fixed.field(&FieldValue::Int((-150000000).into())), "-1.5");
+        assert_eq!(
+            NumberFormat::Signed.field(&FieldValue::Uint(U256::MAX)),
+            U256::MAX.to_string()
+        );
+        assert_eq!(NumberFormat::Hex.field(&FieldValue::Uint(10.into())), "10");
+    }
+}
@@ -0,0 +1,530 @@
+use crate::tracker::types::*;
+
+use std::fs;
+
+use ethers::types::{
+    I256,
+    U256,
+};
+use ethers::utils::{
+    hex,
+    keccak256,
+};
+use serde_json::Value;
+
+// A variable of a storage layout, resolved to where it lives in storage.
+#[derive(Debug, Clone)]
+pub struct StorageVariable {
+    pub path: String,
+    pub slot: U256,
+    // Byte offset of the variable in its slot, counted from the right
+    pub offset: usize,
+    pub size: usize,
+    pub type_label: String,
+    pub encoding: String,
+}
+
+impl StorageVariable {
+    // Decode the value of the slot the variable is in.
+    pub fn decode(&self, value: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let word = slot_word(value)?;
+
+        match self.encoding.as_str() {
+            // The slot of a dynamic array holds its length
+            "dynamic_array" => Ok(U256::from_big_endian(&word).to_string()),
+            "bytes" => decode_short_bytes(&word, &self.type_label),
+            // Mappings don't use their slot
+            "mapping" => Ok(format!("0x{}", hex::encode(word))),
+            _ => {
+                decode_word(value, self.offset, self.size.min(32), &self.type_label)
+                    .map(|decoded| decoded.to_string())
+            }
+        }
+    }
+}
+
+// A step of a variable path, e.g. `balances[0xabc]` is `balances` followed by `Index("0xabc")`.
+#[derive(Debug, Clone)]
+enum PathSegment {
+    Member(String),
+    Index(String),
+}
+
+impl std::fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PathSegment::Member(name) => write!(f, ".{}", name),
+            PathSegment::Index(key) => write!(f, "[{}]", key),
+        }
+    }
+}
+
+impl StorageLayout {
+    // Load a storage layout from a file, either the `storageLayout` itself or a forge
+    // artifact/solc output that contains one.
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut json: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if json.get("storageLayout").is_some() {
+            json = json["storageLayout"].take();
+        }
+
+        serde_json::from_value(json)
+            .map_err(|e| format!("{} is not a valid storage layout: {}", path, e).into())
+    }
+
+    fn type_of(&self, type_id: &str) -> Result<&StorageType, Box<dyn std::error::Error>> {
+        self.types
+            .as_ref()
+            .and_then(|types| types.get(type_id))
+            .ok_or(format!("Type {} is missing from the storage layout", type_id).into())
+    }
+
+    // Resolve a variable path like `balances[0xabc...]`, `config.fee` or `positions[3].owner`
+    // to its slot, offset and type.
+    pub fn resolve(&self, path: &str) -> Result<StorageVariable, Box<dyn std::error::Error>> {
+        let (root, segments) = parse_path(path)?;
+
+        let entry = self
+            .storage
+            .iter()
+            .find(|entry| entry.label == root)
+            .ok_or(format!("No variable {} in the storage layout", root))?;
+        let mut slot = U256::from_dec_str(&entry.slot)?;
+        let mut offset = entry.offset;
+        let mut type_id = entry.type_id.clone();
+
+        for segment in segments {
+            let ty = self.type_of(&type_id)?;
+
+            match (&segment, ty.encoding.as_str()) {
+                (PathSegment::Index(key), "mapping") => {
+                    let key_type = self.type_of(ty.key.as_deref().unwrap_or_default())?;
+                    let mut preimage = encode_key(key, &key_type.label)?;
+                    preimage.extend_from_slice(&u256_bytes(slot));
+
+                    slot = U256::from_big_endian(&keccak256(preimage));
+                    offset = 0;
+                    type_id = ty.value.clone().unwrap_or_default();
+                }
+                (PathSegment::Index(index), "dynamic_array") => {
+                    let base = U256::from_big_endian(&keccak256(u256_bytes(slot)));
+                    let element_id = ty.base.clone().unwrap_or_default();
+                    (slot, offset) = self.array_element(base, parse_index(index)?, &element_id)?;
+                    type_id = element_id;
+                }
+                (PathSegment::Index(index), "inplace") if ty.base.is_some() => {
+                    let index = parse_index(index)?;
+                    if let Some(length) = static_array_length(&ty.label) {
+                        if index >= length {
+                            return Err(format!(
+                                "Index {} is out of bounds for {}",
+                                index, ty.label
+                            )
+                            .into());
+                        }
+                    }
+                    let element_id = ty.base.clone().unwrap_or_default();
+                    (slot, offset) = self.array_element(slot, index, &element_id)?;
+                    type_id = element_id;
+                }
+                (PathSegment::Member(name), "inplace") if ty.members.is_some() => {
+                    let member = ty
+                        .members
+                        .iter()
+                        .flatten()
+                        .find(|member| &member.label == name)
+                        .ok_or(format!("{} has no member {}", ty.label, name))?;
+
+                    slot += U256::from_dec_str(&member.slot)?;
+                    offset = member.offset;
+                    type_id = member.type_id.clone();
+                }
+                _ => {
+                    return Err(format!("Can't access {} of a {}", segment, ty.label).into());
+                }
+            }
+        }
+
+        let ty = self.type_of(&type_id)?;
+        let size: usize = ty.number_of_bytes.parse()?;
+        if ty.encoding == "inplace" && size > 32 {
+            println!("!!! \x1b[93mWARNING:\x1b[0m {} ({}) spans {} slots, only its first slot is tracked !!!", path, ty.label, size.div_ceil(32));
+        }
+
+        Ok(StorageVariable {
+            path: path.to_string(),
+            slot,
+            offset,
+            size,
+            type_label: ty.label.clone(),
+            encoding: ty.encoding.clone(),
+        })
+    }
+
+    // Slot and offset of element `index` of an array whose elements start at `base`.
+    // Elements of 16 bytes or less get packed into shared slots.
+    fn array_element(
+        &self,
+        base: U256,
+        index: u64,
+        element_id: &str,
+    ) -> Result<(U256, usize), Box<dyn std::error::Error>> {
+        let size: usize = self.type_of(element_id)?.number_of_bytes.parse()?;
+
+        match size <= 16 {
+            true => {
+                let per_slot = (32 / size) as u64;
+                Ok((base + index / per_slot, (index % per_slot) as usize * size))
+            }
+            false => Ok((base + U256::from(index) * size.div_ceil(32), 0)),
+        }
+    }
+}
+
+// Get the slot for `--storage_slot`, or resolve `--variable` with `--storage_layout`.
+pub fn resolve_storage_slot(
+    storage_slot: Option<&String>,
+    storage_layout: Option<&String>,
+    variable: Option<&String>,
+) -> Result<(U256, Option<StorageVariable>), Box<dyn std::error::Error>> {
+    match (storage_slot, storage_layout, variable) {
+        (_, Some(storage_layout), Some(variable)) => {
+            let variable = StorageLayout::load(storage_layout)?.resolve(variable)?;
+            println!(
+                "Resolved {} to slot {} at offset {} ({})",
+                variable.path, variable.slot, variable.offset, variable.type_label
+            );
+            Ok((variable.slot, Some(variable)))
+        }
+        (Some(storage_slot), _, None) => Ok((U256::from_dec_str(storage_slot)?, None)),
+        (_, None, Some(_)) => Err("--variable needs a --storage_layout to resolve it".into()),
+        (None, _, None) => Err("Either --storage_slot or --variable has to be set".into()),
+    }
+}
+
+fn parse_path(path: &str) -> Result<(String, Vec<PathSegment>), Box<dyn std::error::Error>> {
+    let invalid = || format!("Invalid variable path: {}", path);
+
+    let end = path.find(['.', '[']).unwrap_or(path.len());
+    let root = path[..end].trim().to_string();
+    if root.is_empty() {
+        return Err(invalid().into());
+    }
+
+    let mut segments = Vec::new();
+    let mut rest = &path[end..];
+    while !rest.is_empty() {
+        if let Some(member) = rest.strip_prefix('.') {
+            let end = member.find(['.', '[']).unwrap_or(member.len());
+            if end == 0 {
+                return Err(invalid().into());
+            }
+            segments.push(PathSegment::Member(member[..end].trim().to_string()));
+            rest = &member[end..];
+        } else if let Some(index) = rest.strip_prefix('[') {
+            let end = index.find(']').ok_or_else(invalid)?;
+            segments.push(PathSegment::Index(index[..end].trim().to_string()));
+            rest = &index[end + 1..];
+        } else {
+            return Err(invalid().into());
+        }
+    }
+
+    Ok((root, segments))
+}
+
+fn parse_index(index: &str) -> Result<u64, Box<dyn std::error::Error>> {
+    match index.strip_prefix("0x") {
+        Some(hex) => Ok(u64::from_str_radix(hex, 16)?),
+        None => Ok(index.parse()?),
+    }
+}
+
+// Length of a static array from its label, e.g. 3 for `uint256[3]`.
+fn static_array_length(label: &str) -> Option<u64> {
+    let start = label.rfind('[')?;
+    label[start + 1..label.len() - 1].parse().ok()
+}
+
+fn u256_bytes(value: U256) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    bytes
+}
+
+fn parse_uint(value: &str) -> Result<U256, Box<dyn std::error::Error>> {
+    match value.strip_prefix("0x") {
+        Some(hex) => Ok(U256::from_str_radix(hex, 16)?),
+        None => Ok(U256::from_dec_str(value)?),
+    }
+}
+
+// Encode a mapping key the way solidity hashes it. Value types are padded to 32 bytes,
+// while `string` and `bytes` keys are hashed as is.
+fn encode_key(key: &str, key_type: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut word = [0u8; 32];
+
+    if key_type == "string" {
+        return Ok(key.trim_matches('"').as_bytes().to_vec());
+    } else if key_type == "bytes" {
+        return Ok(hex::decode(key.trim_start_matches("0x"))?);
+    } else if key_type == "bool" {
+        word[31] = match key {
+            "true" => 1,
+            "false" => 0,
+            _ => return Err(format!("Invalid bool key: {}", key).into()),
+        };
+    } else if key_type.starts_with("address") || key_type.starts_with("contract") {
+        let address = hex::decode(key.trim_start_matches("0x"))?;
+        if address.len() != 20 {
+            return Err(format!("Invalid address key: {}", key).into());
+        }
+        word[12..].copy_from_slice(&address);
+    } else if key_type.starts_with("bytes") {
+        let bytes = hex::decode(key.trim_start_matches("0x"))?;
+        if bytes.len() > 32 {
+            return Err(format!("Invalid {} key: {}", key_type, key).into());
+        }
+        word[..bytes.len()].copy_from_slice(&bytes);
+    } else if key_type.starts_with("int") {
+        let value = match key.strip_prefix("0x") {
+            Some(_) => I256::from_raw(parse_uint(key)?),
+            None => I256::from_dec_str(key)?,
+        };
+        value.into_raw().to_big_endian(&mut word);
+    } else if key_type.starts_with("uint") || key_type.starts_with("enum") {
+        parse_uint(key)?.to_big_endian(&mut word);
+    } else {
+        return Err(format!("Unsupported mapping key type: {}", key_type).into());
+    }
+
+    Ok(word.to_vec())
+}
+
+// The 32 bytes of a slot value, which nodes can return without leading zeroes.
+fn slot_word(value: &str) -> Result<[u8; 32], Box<dyn std::error::Error>> {
+    let hex = value.trim_start_matches("0x");
+    if hex.len() > 64 {
+        return Err(format!("Slot value {} is longer than 32 bytes", value).into());
+    }
+
+    let mut word = [0u8; 32];
+    word.copy_from_slice(&hex::decode(format!("{:0>64}", hex))?);
+    Ok(word)
+}
+
+// Decode `size` bytes at `offset` (counted from the right) of a slot value as `type_label`.
+pub fn decode_word(
+    value: &str,
+    offset: usize,
+    size: usize,
+    type_label: &str,
+) -> Result<FieldValue, Box<dyn std::error::Error>> {
+    if size == 0 || offset + size > 32 {
+        return Err(format!("{} bytes at offset {} don't fit in a slot", size, offset).into());
+    }
+    let word = slot_word(value)?;
+    let bytes = &word[32 - offset - size..32 - offset];
+
+    let mut padded = [0u8; 32];
+    padded[32 - size..].copy_from_slice(bytes);
+
+    let decoded = if type_label == "bool" {
+        FieldValue::Other((bytes.iter().any(|byte| *byte != 0)).to_string())
+    } else if type_label.starts_with("address") || type_label.starts_with("contract") {
+        FieldValue::Other(format!("0x{}", hex::encode(&padded[12..])))
+    } else if type_label.starts_with("bytes") {
+        FieldValue::Other(format!("0x{}", hex::encode(bytes)))
+    } else if type_label.starts_with("int") {
+        // Sign extend to 256 bits
+        if bytes[0] & 0x80 != 0 {
+            padded[..32 - size].fill(0xff);
+        }
+        FieldValue::Int(I256::from_raw(U256::from_big_endian(&padded)))
+    } else if type_label.starts_with("uint") || type_label.starts_with("enum") {
+        FieldValue::Uint(U256::from_big_endian(&padded))
+    } else {
+        FieldValue::Other(format!("0x{}", hex::encode(bytes)))
+    };
+
+    Ok(decoded)
+}
+
+// `string` and `bytes` shorter than 32 bytes are stored in their slot, with the length
+// times two in the last byte. Longer ones store the length times two plus one, and the
+// data at keccak256(slot).
+fn decode_short_bytes(
+    word: &[u8; 32],
+    type_label: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let last = word[31];
+    if last & 1 == 1 {
+        let length = (U256::from_big_endian(word) - 1) / 2;
+        return Ok(format!("<{} bytes>", length));
+    }
+
+    let length = (last / 2) as usize;
+    if length > 31 {
+        return Err(format!(
+            "Invalid {} slot, a short value can't be {} bytes long",
+            type_label, length
+        )
+        .into());
+    }
+
+    let data = &word[..length];
+    Ok(match type_label {
+        "string" => String::from_utf8_lossy(data).to_string(),
+        _ => format!("0x{}", hex::encode(data)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // keccak256 of the words 0 and 1, where the elements of dynamic arrays in slots 0 and 1 start
+    const ARRAY_0: &str = "0x290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563";
+    const ARRAY_1: &str = "0xb10e2d527612073b26eecdfd717e6a320cf44b4afac2b0732d9fcbe2b7fa0cf6";
+
+    fn layout() -> StorageLayout {
+        serde_json::from_str(
+            r#"{
+                "storage": [
+                    {"label": "small", "offset": 0, "slot": "0", "type": "t_array(t_uint64)dyn_storage"},
+                    {"label": "configs", "offset": 0, "slot": "1", "type": "t_array(t_struct(Config))dyn_storage"},
+                    {"label": "owner", "offset": 0, "slot": "2", "type": "t_address"},
+                    {"label": "paused", "offset": 20, "slot": "2", "type": "t_bool"},
+                    {"label": "balances", "offset": 0, "slot": "3", "type": "t_mapping(t_address,t_uint256)"},
+                    {"label": "counts", "offset": 0, "slot": "4", "type": "t_mapping(t_uint256,t_uint256)"},
+                    {"label": "config", "offset": 0, "slot": "5", "type": "t_struct(Config)"},
+                    {"label": "bytes_", "offset": 0, "slot": "7", "type": "t_array(t_uint8)40_storage"}
+                ],
+                "types": {
+                    "t_address": {"encoding": "inplace", "label": "address", "numberOfBytes": "20"},
+                    "t_bool": {"encoding": "inplace", "label": "bool", "numberOfBytes": "1"},
+                    "t_uint8": {"encoding": "inplace", "label": "uint8", "numberOfBytes": "1"},
+                    "t_uint64": {"encoding": "inplace", "label": "uint64", "numberOfBytes": "8"},
+                    "t_uint128": {"encoding": "inplace", "label": "uint128", "numberOfBytes": "16"},
+                    "t_uint256": {"encoding": "inplace", "label": "uint256", "numberOfBytes": "32"},
+                    "t_array(t_uint64)dyn_storage": {"encoding": "dynamic_array", "label": "uint64[]", "numberOfBytes": "32", "base": "t_uint64"},
+                    "t_array(t_uint8)40_storage": {"encoding": "inplace", "label": "uint8[40]", "numberOfBytes": "64", "base": "t_uint8"},
+                    "t_array(t_struct(Config))dyn_storage": {"encoding": "dynamic_array", "label": "struct Config[]", "numberOfBytes": "32", "base": "t_struct(Config)"},
+                    "t_mapping(t_address,t_uint256)": {"encoding": "mapping", "label": "mapping(address => uint256)", "numberOfBytes": "32", "key": "t_address", "value": "t_uint256"},
+                    "t_mapping(t_uint256,t_uint256)": {"encoding": "mapping", "label": "mapping(uint256 => uint256)", "numberOfBytes": "32", "key": "t_uint256", "value": "t_uint256"},
+                    "t_struct(Config)": {"encoding": "inplace", "label": "struct Config", "numberOfBytes": "64", "members": [
+                        {"label": "fee", "offset": 0, "slot": "0", "type": "t_uint128"},
+                        {"label": "limit", "offset": 16, "slot": "0", "type": "t_uint128"},
+                        {"label": "admin", "offset": 0, "slot": "1", "type": "t_address"}
+                    ]}
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn slot(hex: &str) -> U256 {
+        U256::from_str_radix(hex, 16).unwrap()
+    }
+
+    #[test]
+    fn resolves_packed_variables() {
+        let paused = layout().resolve("paused").unwrap();
+        assert_eq!(paused.slot, 2.into());
+        assert_eq!(paused.offset, 20);
+        assert_eq!(paused.size, 1);
+    }
+
+    // Where solidity puts the entry of a mapping in `slot`, keccak256(key . slot).
+    fn mapping_slot(key: &str, slot: u64) -> U256 {
+        let preimage = hex::decode(format!("{:0>64}{:064x}", key, slot)).unwrap();
+        U256::from_big_endian(&keccak256(preimage))
+    }
+
+    #[test]
+    fn resolves_mapping_entries() {
+        let counts = layout().resolve("counts[42]").unwrap();
+        assert_eq!(counts.slot, mapping_slot("2a", 4));
+        // Hex and decimal keys are the same key
+        assert_eq!(layout().resolve("counts[0x2a]").unwrap().slot, counts.slot);
+
+        let address = "1c479675ad559dc151f6ec7ed3fbf8cee79582b6";
+        let balances = layout()
+            .resolve(&format!("balances[0x{}]", address))
+            .unwrap();
+        assert_eq!(balances.slot, mapping_slot(address, 3));
+        assert_eq!(balances.offset, 0);
+        assert_eq!(balances.type_label, "uint256");
+    }
+
+    #[test]
+    fn resolves_packed_array_elements() {
+        // Four uint64 fit in a slot
+        let element = layout().resolve("small[5]").unwrap();
+        assert_eq!(element.slot, slot(ARRAY_0) + 1);
+        assert_eq!(element.offset, 8);
+
+        let element = layout().resolve("bytes_[33]").unwrap();
+        assert_eq!(element.slot, 8.into());
+        assert_eq!(element.offset, 1);
+
+        assert!(layout().resolve("bytes_[40]").is_err());
+    }
+
+    #[test]
+    fn resolves_struct_members() {
+        let limit = layout().resolve("config.limit").unwrap();
+        assert_eq!(limit.slot, 5.into());
+        assert_eq!(limit.offset, 16);
+
+        // Structs in arrays take up whole slots
+        let admin = layout().resolve("configs[2].admin").unwrap();
+        assert_eq!(admin.slot, slot(ARRAY_1) + 5);
+        assert_eq!(admin.offset, 0);
+        assert_eq!(admin.type_label, "address");
+    }
+
+    #[test]
+    fn rejects_invalid_paths() {
+        assert!(layout().resolve("missing").is_err());
+        assert!(layout().resolve("owner.fee").is_err());
+        assert!(layout().resolve("config.missing").is_err());
+        assert!(layout().resolve("balances[0x12]").is_err());
+        assert!(layout().resolve("small[1").is_err());
+    }
+
+    #[test]
+    fn decodes_packed_words() {
+        let value = "0x000000000000000000000001ffffffffffffffffffffffffffffffffffffff85";
+        assert_eq!(
+            decode_word(value, 0, 1, "int8").unwrap(),
+            FieldValue::Int(I256::from(-123))
+        );
+        assert_eq!(
+            decode_word(value, 1, 19, "uint152").unwrap(),
+            FieldValue::Uint((U256::one() << 152) - 1)
+        );
+        assert_eq!(
+            decode_word(value, 20, 1, "bool").unwrap().to_string(),
+            "true"
+        );
+        assert!(decode_word(value, 31, 2, "uint16").is_err());
+    }
+
+    #[test]
+    fn decodes_short_strings() {
+        let variable = StorageVariable {
+            path: "name".to_string(),
+            slot: 0.into(),
+            offset: 0,
+            size: 32,
+            type_label: "string".to_string(),
+            encoding: "bytes".to_string(),
+        };
+        let value = format!("0x{:0<62}{:02x}", hex::encode("sothis"), 12);
+        assert_eq!(variable.decode(&value).unwrap(), "sothis");
+        assert_eq!(variable.decode("0x41").unwrap(), "<32 bytes>");
+        // An even length byte above 62 doesn't fit in the slot
+        assert!(variable.decode("0x42").is_err());
+    }
+}
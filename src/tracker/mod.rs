@@ -1,6 +1,16 @@
+pub mod annotate;
+pub mod bisect;
 pub mod call_track;
 mod common;
+pub mod decoder;
 pub mod fast_track;
-mod time;
+pub mod fetch;
+pub mod format;
+pub mod layout;
+pub mod multi_track;
+pub mod output;
+pub mod signature;
+pub mod time;
+#[allow(clippy::module_inception)]
 pub mod tracker;
 pub mod types;
@@ -0,0 +1,416 @@
+use crate::rpc::format::hex_to_decimal;
+use crate::rpc::types::CallParams;
+use crate::tracker::annotate::Annotator;
+use crate::tracker::common::record_change;
+use crate::tracker::decoder::StateDecoder;
+use crate::tracker::fetch::{
+    Query,
+    RangeFetcher,
+};
+use crate::tracker::format::NumberFormat;
+use crate::tracker::output::ChangeWriter;
+use crate::tracker::signature::resolve_calldata;
+use crate::tracker::time::get_latest_unix_timestamp;
+use crate::tracker::types::*;
+use crate::RpcConnection;
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::sync::Arc;
+
+use ctrlc;
+use ethers::types::U256;
+
+// Track every target of `spec_file` over the same block range. All targets of a
+// block get fetched in a single JSON-RPC batch, or one by one if the node doesn't
+// support batching. A target that fails at a block is skipped there, the others
+// keep getting tracked.
+#[allow(clippy::too_many_arguments)]
+pub async fn multi_track(
+    source_rpc: RpcConnection,
+    spec_file: String,
+    terminal_block: Option<u64>,
+    origin_block: u64,
+    query_interval: Option<u64>,
+    concurrency: usize,
+    chunk_size: u64,
+    number_format: NumberFormat,
+    path: String,
+    filename: String,
+    resume: bool,
+    annotate: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let interrupted_clone = interrupted.clone();
+
+    let spec: TrackSpec = serde_json::from_str(&fs::read_to_string(&spec_file)?)?;
+    if spec.targets.is_empty() {
+        return Err("Tracking spec has no targets".into());
+    }
+    if !["json", "ndjson", "csv"].contains(&spec.format.as_str()) {
+        return Err(format!(
+            "Invalid format {} in the tracking spec, use json, ndjson or csv",
+            spec.format
+        )
+        .into());
+    }
+
+    // Set how much we're tracking by
+    // Default is that we are checking every block for state changes
+    let mut interval = 1;
+
+    // Print warning that sothis does not have the full context
+    if let Some(query_interval) = query_interval {
+        println!("!!! \x1b[93mWARNING:\x1b[0m Query interval is set, sothis will not have the full context of the state changes !!!");
+        if annotate && query_interval > 1 {
+            println!("!!! \x1b[93mWARNING:\x1b[0m --annotate only looks at the block a change was found at, transactions in the blocks skipped before it are missed !!!");
+        }
+        interval = query_interval;
+    }
+
+    ctrlc::set_handler(move || {
+        interrupted_clone.store(true, Ordering::SeqCst);
+    })?;
+
+    let layout = spec
+        .storage_layout
+        .as_deref()
+        .map(StorageLayout::load)
+        .transpose()?;
+
+    let mut queries = Vec::new();
+    let mut storage = BTreeMap::new();
+    for target in &spec.targets {
+        let slot = match (&target.slot, &target.variable) {
+            (Some(slot), None) => {
+                let slot = match slot.starts_with("0x") {
+                    true => U256::from_str_radix(slot, 16)?,
+                    false => U256::from_dec_str(slot)?,
+                };
+                Some((slot, None))
+            }
+            (None, Some(variable)) => {
+                let variable = layout
+                    .as_ref()
+                    .ok_or("Tracking a `variable` needs a `storage_layout` in the spec")?
+                    .resolve(variable)?;
+                println!(
+                    "Resolved {} to slot {} at offset {} ({})",
+                    variable.path, variable.slot, variable.offset, variable.type_label
+                );
+                Some((variable.slot, Some(variable)))
+            }
+            (None, None) => None,
+            _ => {
+                return Err(format!(
+                    "Target {} can't have both a `slot` and a `variable`",
+                    target.name
+                )
+                .into())
+            }
+        };
+
+        let call = match (&target.calldata, &target.signature) {
+            (None, None) => None,
+            (calldata, signature) => {
+                Some(resolve_calldata(
+                    calldata.as_ref(),
+                    signature.as_ref(),
+                    &target.args,
+                )?)
+            }
+        };
+
+        let (query, decoder, list) = match (slot, call) {
+            (Some((slot, variable)), None) => {
+                (
+                    Query::Slot(slot),
+                    StateDecoder::new(variable, None, target.fields.clone(), target.watch.clone())?,
+                    TargetChangeList::Slot(StateChangeList {
+                        address: target.address.clone(),
+                        storage_slot: slot,
+                        state_changes: Vec::new(),
+                    }),
+                )
+            }
+            (None, Some((calldata, signature))) => {
+                (
+                    Query::Call(CallParams {
+                        from: Value::Null,
+                        to: target.address.clone(),
+                        data: calldata.clone(),
+                    }),
+                    StateDecoder::new(
+                        None,
+                        signature,
+                        target.fields.clone(),
+                        target.watch.clone(),
+                    )?,
+                    TargetChangeList::Call(CallChangeList {
+                        address: target.address.clone(),
+                        calldata,
+                        state_changes: Vec::new(),
+                    }),
+                )
+            }
+            _ => {
+                return Err(format!(
+                    "Target {} needs exactly one of `slot`, `variable`, `calldata` or `signature`",
+                    target.name
+                )
+                .into())
+            }
+        };
+
+        if storage.insert(target.name.clone(), list).is_some() {
+            return Err(format!("Target name {} is used more than once", target.name).into());
+        }
+        queries.push((target, query, decoder));
+    }
+
+    let terminal_block = match terminal_block {
+        Some(terminal_block) => terminal_block,
+        None => {
+            let a = hex_to_decimal(&source_rpc.block_number().await?)?;
+            println!(
+                "No terminal block set, setting terminal block to current head: {}",
+                a
+            );
+            a
+        }
+    };
+
+    // Error out if the origin block is >= than the terminal
+    if origin_block >= terminal_block {
+        return Err("Origin block cannot be higher than the terminal block".into());
+    }
+
+    // Loaded changes are decoded like new ones of their target
+    let decode = |name: &str, change: StateChange| {
+        let (_, _, decoder) = queries
+            .iter()
+            .find(|(target, _, _)| target.name == name)
+            .ok_or(format!("Unknown target {}", name))?;
+        decoder.state_change(change.block_number, change.value)
+    };
+    let columns: BTreeMap<&str, CsvColumns> = queries
+        .iter()
+        .map(|(target, _, decoder)| (target.name.as_str(), decoder.csv_columns(annotate)))
+        .collect();
+
+    let mut writer = match spec.combined {
+        true => {
+            let filename = match filename.as_str() {
+                "" if resume => return Err("--resume needs the --filename to continue".into()),
+                "" => {
+                    format!(
+                        "multi-track-timestamp-{}.{}",
+                        get_latest_unix_timestamp(),
+                        spec.format
+                    )
+                }
+                _ => filename,
+            };
+            let mut combined = CombinedChangeList { targets: storage };
+            let writer = ChangeWriter::open(
+                &path,
+                &filename,
+                &mut combined,
+                resume,
+                number_format,
+                combined_columns(columns.values().cloned().collect(), annotate),
+                |target, change| decode(target.ok_or("Change has no target")?, change),
+            )?;
+            storage = combined.targets;
+            TargetWriter::Combined(writer)
+        }
+        false => {
+            let mut writers = BTreeMap::new();
+            for (name, list) in storage.iter_mut() {
+                let filename = format!("{}.{}", name, spec.format);
+                writers.insert(
+                    name.clone(),
+                    ChangeWriter::open(
+                        &path,
+                        &filename,
+                        list,
+                        resume,
+                        number_format,
+                        columns[name.as_str()].clone(),
+                        |_, change| decode(name, change),
+                    )?,
+                );
+            }
+            TargetWriter::PerTarget(writers)
+        }
+    };
+
+    // Continue after the last block the run we're resuming scanned, or its last change.
+    // Targets are queried together, so every target was tracked until the latest of them.
+    let mut origin_block = origin_block;
+    if let Some(last) = writer.resumed_until().or(storage
+        .values()
+        .filter_map(|list| list.state_changes().last())
+        .map(|change| change.block_number.as_u64())
+        .max())
+    {
+        origin_block = origin_block.max(last + interval);
+        println!("Resuming from block {}", origin_block);
+    }
+
+    // Changes found before an error are still written out
+    let result: Result<(), Box<dyn std::error::Error>> = async {
+        let mut annotator = annotate.then(|| Annotator::new(&source_rpc));
+        let mut fetcher = RangeFetcher::with_targets(
+            &source_rpc,
+            queries
+                .iter()
+                .map(|(target, query, _)| (target.address.clone(), query.clone()))
+                .collect(),
+            origin_block,
+            terminal_block,
+            interval,
+            concurrency,
+            chunk_size,
+            &interrupted,
+        );
+        let mut failed = 0;
+
+        while let Some((block, results)) = fetcher.next_block().await? {
+            for ((target, query, decoder), result) in queries.iter().zip(results) {
+                // Keep tracking the other targets, this one gets compared again at the next block
+                let value = match result {
+                    Ok(value) => value,
+                    Err(e) => {
+                        failed += 1;
+                        println!(
+                            "!!! \x1b[93mWARNING:\x1b[0m Skipping {}: {} !!!",
+                            target.name, e
+                        );
+                        continue;
+                    }
+                };
+                let change = decoder.state_change(block.into(), TrackedValue::parse(&value))?;
+                record_change(
+                    change,
+                    storage
+                        .get_mut(&target.name)
+                        .expect("Target is missing")
+                        .state_changes_mut(),
+                    decoder,
+                    &mut annotator,
+                    (&target.address, query),
+                    &format!("value of {}", target.name),
+                    |change| writer.append(&target.name, change),
+                )
+                .await?;
+            }
+            writer.scanned(block)?;
+        }
+
+        if failed > 0 {
+            println!(
+                "!!! \x1b[93mWARNING:\x1b[0m {} target queries failed and were skipped, changes around them can be missing or late !!!",
+                failed
+            );
+        }
+        Ok(())
+    }
+    .await;
+
+    writer.finish(storage)?;
+    result?;
+
+    Ok(())
+}
+
+// Where the changes of `multi_track` are written to, either one file for all targets
+// or a file per target.
+enum TargetWriter {
+    Combined(ChangeWriter),
+    PerTarget(BTreeMap<String, ChangeWriter>),
+}
+
+impl TargetWriter {
+    fn append(
+        &mut self,
+        name: &str,
+        change: &StateChange,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            TargetWriter::Combined(writer) => writer.append(Some(name), change),
+            TargetWriter::PerTarget(writers) => {
+                writers
+                    .get_mut(name)
+                    .expect("Target is missing")
+                    .append(None, change)
+            }
+        }
+    }
+
+    fn scanned(&mut self, block: u64) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            TargetWriter::Combined(writer) => writer.scanned(block),
+            TargetWriter::PerTarget(writers) => {
+                for writer in writers.values_mut() {
+                    writer.scanned(block)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // The last block the run we're resuming scanned. A file per target is synced one
+    // after another, so the earliest of them is the one every target got to.
+    fn resumed_until(&self) -> Option<u64> {
+        match self {
+            TargetWriter::Combined(writer) => writer.resumed_until(),
+            TargetWriter::PerTarget(writers) => {
+                writers
+                    .values()
+                    .map(|writer| writer.resumed_until())
+                    .min()
+                    .flatten()
+            }
+        }
+    }
+
+    fn finish(
+        self,
+        storage: BTreeMap<String, TargetChangeList>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            TargetWriter::Combined(writer) => {
+                writer.finish(&CombinedChangeList { targets: storage })
+            }
+            TargetWriter::PerTarget(writers) => {
+                for (name, writer) in writers {
+                    writer.finish(&storage[&name])?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Rows of a combined CSV file are padded to the widest target, so the annotations line up.
+// The decoded columns are only named if every target has the same ones.
+fn combined_columns(columns: Vec<CsvColumns>, annotated: bool) -> CsvColumns {
+    if columns.windows(2).all(|pair| pair[0] == pair[1]) {
+        return columns.into_iter().next().unwrap_or_default();
+    }
+    let width = columns
+        .iter()
+        .map(|columns| columns.decoded.len())
+        .max()
+        .unwrap_or(0);
+    CsvColumns {
+        decoded: (1..=width).map(|i| format!("column{}", i)).collect(),
+        annotated,
+    }
+}
@@ -0,0 +1,434 @@
+use crate::tracker::format::NumberFormat;
+use crate::tracker::types::{
+    csv_split,
+    CsvColumns,
+    SerializeStorage,
+    StateChange,
+    TrackedValue,
+};
+
+use std::fs::{
+    self,
+    File,
+    OpenOptions,
+};
+use std::io::{
+    BufWriter,
+    Write,
+};
+use std::path::Path;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use ethers::types::U256;
+use serde::de::DeserializeOwned;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+
+// How often appended changes get synced to disk.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Json,
+    Ndjson,
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn from_filename(filename: &str) -> Self {
+        match filename {
+            filename if filename.ends_with(".csv") => OutputFormat::Csv,
+            filename if filename.ends_with(".ndjson") => OutputFormat::Ndjson,
+            _ => OutputFormat::Json,
+        }
+    }
+}
+
+// What a `.progress` file next to the output records: what is tracked, and the last
+// block that was queried, which can be well past the last change.
+#[derive(Debug, Deserialize, Serialize)]
+struct Progress {
+    tracking: Value,
+    scanned_until: Option<u64>,
+}
+
+// Appends every change to disk as soon as it's found, so a killed tracker doesn't lose
+// what it tracked so far.
+//
+// CSV and NDJSON outputs are written to directly. CSV starts with a header row naming the
+// columns, NDJSON with the tracked list without any changes, followed by a line per
+// change. JSON can't be appended to, so the changes are streamed to a `.ndjson` journal
+// next to it, which is replaced by the JSON document once tracking is done. With every sync, the last scanned block is written to
+// a `.progress` file, so `--resume` continues from there instead of the last change.
+pub struct ChangeWriter {
+    path: String,
+    format: OutputFormat,
+    journal: Option<String>,
+    file: BufWriter<File>,
+    number_format: NumberFormat,
+    columns: CsvColumns,
+    last_sync: Instant,
+    progress: Progress,
+    // Where the run we resumed stopped scanning, if it recorded that
+    resumed_until: Option<u64>,
+}
+
+impl ChangeWriter {
+    // Start writing to `{path}/{filename}`. With `resume`, the changes of an earlier run are
+    // loaded from it into `storage` first, re-decoded with `decode`, and kept in the file.
+    // `columns` are the columns of CSV rows.
+    pub fn open<T, F>(
+        path: &str,
+        filename: &str,
+        storage: &mut T,
+        resume: bool,
+        number_format: NumberFormat,
+        columns: CsvColumns,
+        decode: F,
+    ) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        T: SerializeStorage + Clone + DeserializeOwned,
+        F: Fn(Option<&str>, StateChange) -> Result<StateChange, Box<dyn std::error::Error>>,
+    {
+        let format = OutputFormat::from_filename(filename);
+        let path = format!("{}/{}", path, filename);
+        let journal = match format {
+            OutputFormat::Json => Some(format!("{}.ndjson", path)),
+            _ => None,
+        };
+
+        // A journal is only left behind by a run that didn't finish, don't lose its changes
+        if let Some(journal) = journal
+            .as_ref()
+            .filter(|journal| Path::new(journal).exists())
+        {
+            if !resume {
+                return Err(format!(
+                    "{} is left over from a run that didn't finish, pass --resume to continue it or remove it",
+                    journal
+                )
+                .into());
+            }
+        }
+
+        let header = storage.clone();
+        let mut resumed_until = None;
+        if resume {
+            // Formatted values can't always be turned back into what the node returned
+            if number_format != NumberFormat::Hex {
+                return Err("--resume needs the values to be written as hex".into());
+            }
+            resumed_until = load_progress(&path, format, storage)?;
+            load(&path, journal.as_ref(), format, storage, &columns, decode)?;
+        }
+
+        let stream_path = journal.clone().unwrap_or(path.clone());
+        println!("Writing changes to file: {}", stream_path);
+
+        // Write to a temporary file first, so a file we resume from is never half rewritten
+        let tmp = format!("{}.tmp", stream_path);
+        let mut writer = Self {
+            file: BufWriter::new(File::create(&tmp)?),
+            path,
+            format,
+            journal,
+            number_format,
+            columns,
+            last_sync: Instant::now(),
+            progress: Progress {
+                tracking: serde_json::from_str(&header.serialize_json(NumberFormat::Hex)?)?,
+                scanned_until: resumed_until,
+            },
+            resumed_until,
+        };
+
+        let header = match writer.format {
+            OutputFormat::Csv => writer.columns.header(T::TARGETS),
+            _ => header.serialize_json(writer.line_format())?,
+        };
+        writer.write_line(header)?;
+        for (target, change) in storage.changes() {
+            writer.write_change(target, change)?;
+        }
+        writer.sync()?;
+
+        fs::rename(&tmp, &stream_path)?;
+        writer.file = BufWriter::new(OpenOptions::new().append(true).open(&stream_path)?);
+
+        Ok(writer)
+    }
+
+    // Append `change`. `target` is the name of the target it belongs to, if the file
+    // has more than one.
+    pub fn append(
+        &mut self,
+        target: Option<&str>,
+        change: &StateChange,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_change(target, change)?;
+
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    // Note that every block until `block` was queried and its changes appended.
+    pub fn scanned(&mut self, block: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.progress.scanned_until = Some(block);
+
+        if self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    // The last block the run we resumed scanned, if it recorded it.
+    pub fn resumed_until(&self) -> Option<u64> {
+        self.resumed_until
+    }
+
+    // Sync what's left and write the final JSON document, if we're writing JSON.
+    pub fn finish<T: SerializeStorage>(
+        mut self,
+        storage: &T,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.sync()?;
+
+        if let Some(journal) = &self.journal {
+            let json = storage.serialize_json(self.number_format)?;
+
+            // Write to a temporary file first, so we never end up with half a document
+            let tmp = format!("{}.tmp", self.path);
+            let mut file = File::create(&tmp)?;
+            file.write_all(json.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp, &self.path)?;
+            fs::remove_file(journal)?;
+        }
+
+        println!("\nWrote to file: {}", self.path);
+
+        Ok(())
+    }
+
+    fn write_change(
+        &mut self,
+        target: Option<&str>,
+        change: &StateChange,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let number_format = self.line_format();
+        let line = match self.format {
+            OutputFormat::Csv => {
+                let row = change.serialize_csv(number_format, &self.columns);
+                match target {
+                    Some(target) => format!("{},{}", target, row),
+                    None => row,
+                }
+            }
+            _ => serde_json::to_string(&change.formatted(target, number_format))?,
+        };
+        self.write_line(line)
+    }
+
+    // The journal keeps hex values, they're formatted when writing the final document
+    fn line_format(&self) -> NumberFormat {
+        match self.journal {
+            Some(_) => NumberFormat::Hex,
+            None => self.number_format,
+        }
+    }
+
+    fn write_line(&mut self, line: String) -> Result<(), Box<dyn std::error::Error>> {
+        writeln!(self.file, "{}", line)?;
+        // Hand the line to the OS right away, so it survives the process getting killed
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        self.last_sync = Instant::now();
+
+        // Only after the changes are on disk, so the progress never gets ahead of them
+        let progress = format!("{}.progress", self.path);
+        let tmp = format!("{}.tmp", progress);
+        fs::write(&tmp, serde_json::to_string(&self.progress)?)?;
+        fs::rename(&tmp, &progress)?;
+        Ok(())
+    }
+}
+
+// Check the `.progress` file of an earlier run tracks the same as `storage`, and get the
+// last block it scanned. CSV files don't record what they track, so they can only be
+// resumed with one.
+fn load_progress<T>(
+    path: &str,
+    format: OutputFormat,
+    storage: &T,
+) -> Result<Option<u64>, Box<dyn std::error::Error>>
+where
+    T: SerializeStorage + DeserializeOwned,
+{
+    let progress_path = format!("{}.progress", path);
+    if !Path::new(&progress_path).exists() {
+        if format == OutputFormat::Csv && Path::new(path).exists() {
+            return Err(format!(
+                "Can't resume from {}, there's no {} to check what it tracks",
+                path, progress_path
+            )
+            .into());
+        }
+        return Ok(None);
+    }
+
+    let progress: Progress = serde_json::from_str(&fs::read_to_string(&progress_path)?)?;
+    let tracked: T = serde_json::from_value(progress.tracking)?;
+    if let Some(mismatch) = storage.mismatch(&tracked) {
+        return Err(format!("Can't resume from {}, its {}", path, mismatch).into());
+    }
+
+    Ok(progress.scanned_until)
+}
+
+// Load the changes an earlier run wrote to `path` into `storage`, after checking they're
+// of the same slot or call.
+fn load<T, F>(
+    path: &str,
+    journal: Option<&String>,
+    format: OutputFormat,
+    storage: &mut T,
+    columns: &CsvColumns,
+    decode: F,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    T: SerializeStorage + DeserializeOwned,
+    F: Fn(Option<&str>, StateChange) -> Result<StateChange, Box<dyn std::error::Error>>,
+{
+    // A journal is only left behind by a run that didn't finish, so it has the latest changes
+    let (path, format) = match journal {
+        Some(journal) if Path::new(journal).exists() => (journal.as_str(), OutputFormat::Ndjson),
+        _ => (path, format),
+    };
+    if !Path::new(path).exists() {
+        println!(
+            "!!! \x1b[93mWARNING:\x1b[0m {} doesn't exist, nothing to resume from !!!",
+            path
+        );
+        return Ok(());
+    }
+    println!("Resuming from file: {}", path);
+
+    let content = fs::read_to_string(path)?;
+    let mut lines: Vec<&str> = content.lines().filter(|line| !line.is_empty()).collect();
+    // Every line is written with its newline, so a line without one got cut off
+    if format != OutputFormat::Json && !content.is_empty() && !content.ends_with('\n') {
+        println!(
+            "!!! \x1b[93mWARNING:\x1b[0m Ignoring the last line of {}, it was cut off !!!",
+            path
+        );
+        lines.pop();
+    }
+
+    // Changes with the columns after their value, if they're from a CSV file
+    let mut changes: Vec<(Option<String>, StateChange, Vec<String>)> = Vec::new();
+    let header: Option<T> = match format {
+        OutputFormat::Json => {
+            let header: T = serde_json::from_str(&content)?;
+            for (target, change) in header.changes() {
+                changes.push((target.map(str::to_string), change.clone(), Vec::new()));
+            }
+            Some(header)
+        }
+        OutputFormat::Ndjson => {
+            let header = lines.first().ok_or(format!("{} is empty", path))?;
+            for line in &lines[1..] {
+                let mut change: Value = serde_json::from_str(line)?;
+                let target = change
+                    .as_object_mut()
+                    .and_then(|change| change.remove("target"))
+                    .and_then(|target| target.as_str().map(str::to_string));
+                changes.push((target, serde_json::from_value(change)?, Vec::new()));
+            }
+            Some(serde_json::from_str(header)?)
+        }
+        OutputFormat::Csv => {
+            // Annotations are only found in the right columns if they're the same as ours
+            let header = columns.header(T::TARGETS);
+            if let Some(first) = lines.first().filter(|first| **first != header) {
+                return Err(format!(
+                    "Can't resume from {}, its columns are {}, not {}",
+                    path, first, header
+                )
+                .into());
+            }
+            for line in lines.iter().skip(1) {
+                let mut columns = csv_split(line).into_iter();
+                let target = match T::TARGETS {
+                    true => columns.next(),
+                    false => None,
+                };
+                let (block_number, value) = match (columns.next(), columns.next()) {
+                    (Some(block_number), Some(value)) => (block_number, value),
+                    _ => return Err(format!("Invalid row in {}: {}", path, line).into()),
+                };
+                changes.push((
+                    target,
+                    StateChange {
+                        block_number: U256::from_dec_str(&block_number)?,
+                        value: TrackedValue::parse(&value),
+                        ..Default::default()
+                    },
+                    columns.collect(),
+                ));
+            }
+            None
+        }
+    };
+
+    if let Some(mismatch) = header.and_then(|header| storage.mismatch(&header)) {
+        return Err(format!("Can't resume from {}, its {}", path, mismatch).into());
+    }
+
+    let loaded = changes.len();
+    for (target, change, row) in changes {
+        if !change.value.hex().starts_with("0x") {
+            return Err(format!("Can't resume from {}, its values aren't hex", path).into());
+        }
+        let mut decoded = decode(target.as_deref(), change.clone())?;
+
+        // Annotations aren't decoded, so keep the ones we loaded
+        decoded.timestamp = change.timestamp;
+        decoded.transactions = change.transactions;
+        let annotations = row.get(columns.decoded.len()..).unwrap_or_default();
+        if let Some(timestamp) = annotations
+            .first()
+            .filter(|timestamp| !timestamp.is_empty())
+        {
+            decoded.timestamp = Some(U256::from_dec_str(timestamp)?);
+        }
+        if let Some(transactions) = annotations.get(1) {
+            decoded.transactions = Some(
+                transactions
+                    .split(';')
+                    .filter(|hash| !hash.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            );
+        }
+
+        storage.push(target.as_deref(), decoded)?;
+    }
+    println!("Loaded {} changes", loaded);
+
+    Ok(())
+}
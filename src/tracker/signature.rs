@@ -0,0 +1,228 @@
+use crate::tracker::types::{
+    DecodedFields,
+    FieldValue,
+};
+
+use ethers::abi::param_type::Reader;
+use ethers::abi::token::{
+    LenientTokenizer,
+    Tokenizer,
+};
+use ethers::abi::{
+    ParamType,
+    Token,
+};
+use ethers::types::I256;
+use ethers::utils::{
+    hex,
+    keccak256,
+};
+
+// A human readable function signature with its return types, e.g.
+// `latestRoundData()(uint80,int256 answer,uint256,uint256,uint80)`.
+#[derive(Debug, Clone)]
+pub struct CallSignature {
+    pub name: String,
+    pub inputs: Vec<ParamType>,
+    // Return values are named `output{index}` unless the signature names them
+    pub outputs: Vec<(String, ParamType)>,
+}
+
+impl CallSignature {
+    pub fn parse(signature: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let invalid = || format!("Invalid signature: {}", signature);
+
+        let signature = signature.trim().trim_start_matches("function ");
+        let open = signature.find('(').ok_or_else(invalid)?;
+        let name = signature[..open].trim().to_string();
+        let close = matching_paren(signature, open).ok_or_else(invalid)?;
+
+        let inputs = split_params(&signature[open + 1..close])
+            .into_iter()
+            .map(|param| Ok(Reader::read(param_type(param))?))
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+
+        // The return types are either right after the inputs, or after `returns`
+        let rest = signature[close + 1..].trim();
+        let rest = rest.trim_start_matches("returns").trim();
+        let outputs = match rest.is_empty() {
+            true => Vec::new(),
+            false => {
+                if !rest.starts_with('(') || matching_paren(rest, 0) != Some(rest.len() - 1) {
+                    return Err(invalid().into());
+                }
+                split_params(&rest[1..rest.len() - 1])
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, param)| {
+                        let ty = param_type(param);
+                        let name = match param[ty.len()..].split_whitespace().last() {
+                            Some(name) => name.to_string(),
+                            None => format!("output{}", i),
+                        };
+                        Ok((name, Reader::read(ty)?))
+                    })
+                    .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?
+            }
+        };
+
+        if name.is_empty() {
+            return Err(invalid().into());
+        }
+
+        Ok(Self {
+            name,
+            inputs,
+            outputs,
+        })
+    }
+
+    // The canonical signature the selector is derived from, e.g. `transfer(address,uint256)`.
+    pub fn canonical(&self) -> String {
+        let inputs: Vec<String> = self.inputs.iter().map(|input| input.to_string()).collect();
+        format!("{}({})", self.name, inputs.join(","))
+    }
+
+    // ABI encode a call with `args`, which are parsed according to the input types.
+    pub fn encode(&self, args: &[String]) -> Result<String, Box<dyn std::error::Error>> {
+        if args.len() != self.inputs.len() {
+            return Err(format!(
+                "{} takes {} arguments, got {}",
+                self.canonical(),
+                self.inputs.len(),
+                args.len()
+            )
+            .into());
+        }
+
+        let mut tokens = Vec::new();
+        for (param_type, arg) in self.inputs.iter().zip(args) {
+            tokens.push(LenientTokenizer::tokenize(param_type, arg)?);
+        }
+
+        let selector = &keccak256(self.canonical())[..4];
+        Ok(format!(
+            "0x{}{}",
+            hex::encode(selector),
+            hex::encode(ethers::abi::encode(&tokens))
+        ))
+    }
+
+    // Decode the return data of a call into its named return values.
+    pub fn decode(&self, value: &str) -> Result<DecodedFields, Box<dyn std::error::Error>> {
+        let data = hex::decode(value.trim_start_matches("0x"))?;
+        let types: Vec<ParamType> = self.outputs.iter().map(|(_, ty)| ty.clone()).collect();
+        let tokens = ethers::abi::decode(&types, &data)
+            .map_err(|e| format!("Could not decode {} as {:?}: {}", value, types, e))?;
+
+        Ok(DecodedFields(
+            self.outputs
+                .iter()
+                .zip(tokens)
+                .map(|((name, _), token)| (name.clone(), field_value(token)))
+                .collect(),
+        ))
+    }
+}
+
+// Get the calldata for `--calldata`, or encode it from `--signature` and `--args`.
+pub fn resolve_calldata(
+    calldata: Option<&String>,
+    signature: Option<&String>,
+    args: &[String],
+) -> Result<(String, Option<CallSignature>), Box<dyn std::error::Error>> {
+    match (calldata, signature) {
+        (Some(calldata), None) => Ok((calldata.clone(), None)),
+        (None, Some(signature)) => {
+            let signature = CallSignature::parse(signature)?;
+            let calldata = signature.encode(args)?;
+            println!("Encoded {} as: {}", signature.canonical(), calldata);
+            Ok((calldata, Some(signature)))
+        }
+        (Some(_), Some(_)) => Err("Either calldata or a signature can be set, not both".into()),
+        (None, None) => Err("Either calldata or a signature has to be set".into()),
+    }
+}
+
+// Index of the parenthesis closing the one at `open`.
+fn matching_paren(s: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices().skip(open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Split a parameter list on the commas that aren't inside a tuple.
+fn split_params(params: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in params.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                split.push(params[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !params[start..].trim().is_empty() {
+        split.push(params[start..].trim());
+    }
+    split
+}
+
+// The type of a parameter, without its name and data location.
+fn param_type(param: &str) -> &str {
+    let param = param.trim();
+    match param.rfind(')') {
+        // Tuples can have spaces in them, the name can only come after the last parenthesis
+        Some(close) => {
+            let end = param[close..]
+                .find(char::is_whitespace)
+                .map(|i| close + i)
+                .unwrap_or(param.len());
+            &param[..end]
+        }
+        None => param.split_whitespace().next().unwrap_or_default(),
+    }
+}
+
+// Numbers are kept typed, anything else is written out.
+fn field_value(token: Token) -> FieldValue {
+    match token {
+        Token::Uint(value) => FieldValue::Uint(value),
+        Token::Int(value) => FieldValue::Int(I256::from_raw(value)),
+        token => FieldValue::Other(format_token(token)),
+    }
+}
+
+fn format_token(token: Token) -> String {
+    match token {
+        Token::Uint(value) => value.to_string(),
+        Token::Int(value) => I256::from_raw(value).to_string(),
+        Token::Address(address) => format!("{:?}", address),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => value,
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", hex::encode(bytes)),
+        Token::Array(tokens) | Token::FixedArray(tokens) => {
+            let tokens: Vec<String> = tokens.into_iter().map(format_token).collect();
+            format!("[{}]", tokens.join(","))
+        }
+        Token::Tuple(tokens) => {
+            let tokens: Vec<String> = tokens.into_iter().map(format_token).collect();
+            format!("({})", tokens.join(","))
+        }
+    }
+}
@@ -1,5 +1,13 @@
 use crate::rpc::format::hex_to_decimal;
-use crate::tracker::common::set_filename_and_serialize;
+use crate::tracker::annotate::Annotator;
+use crate::tracker::common::{
+    output_filename,
+    record_change,
+};
+use crate::tracker::decoder::StateDecoder;
+use crate::tracker::fetch::Query;
+use crate::tracker::format::NumberFormat;
+use crate::tracker::output::ChangeWriter;
 use crate::tracker::types::*;
 use crate::RpcConnection;
 
@@ -13,15 +21,19 @@ use ctrlc;
 use ethers::types::U256;
 
 // We listen for new blocks and get the storage slot value if changed.
+#[allow(clippy::too_many_arguments)]
 pub async fn track_state(
     source_rpc: RpcConnection,
     storage_slot: U256,
+    decoder: StateDecoder,
     contract_address: String,
     terminal_block: Option<u64>,
     block_listen_time: u64,
-    decimal: bool,
+    number_format: NumberFormat,
     path: String,
     filename: String,
+    resume: bool,
+    annotate: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let interrupted = Arc::new(AtomicBool::new(false));
     let interrupted_clone = interrupted.clone();
@@ -36,52 +48,62 @@ pub async fn track_state(
         state_changes: Vec::new(),
     };
 
-    let mut block_number = source_rpc.block_number().await?;
-    loop {
-        // Crazy hamburger check
-        let has_reached_terminal_block = terminal_block
-            .as_ref()
-            .map(|tb| hex_to_decimal(&block_number).unwrap() >= *tb)
-            .unwrap_or(false);
-        if interrupted.load(Ordering::SeqCst) || has_reached_terminal_block {
-            break;
-        }
+    let filename = output_filename(
+        filename,
+        &contract_address,
+        "slot",
+        storage_slot.to_string(),
+    );
+    let mut writer = ChangeWriter::open(
+        &path,
+        &filename,
+        &mut storage,
+        resume,
+        number_format,
+        decoder.csv_columns(annotate),
+        |_, change| decoder.state_change(change.block_number, change.value),
+    )?;
 
-        let block_number_u256: U256 = block_number.parse()?;
-        let latest_slot = source_rpc
-            .get_storage_at(contract_address.clone(), storage_slot)
-            .await?;
+    // Changes found before an error are still written out
+    let result: Result<(), Box<dyn std::error::Error>> = async {
+        let mut annotator = annotate.then(|| Annotator::new(&source_rpc));
+        let mut block_number = source_rpc.block_number().await?;
+        loop {
+            // Crazy hamburger check
+            let has_reached_terminal_block = terminal_block
+                .as_ref()
+                .map(|tb| hex_to_decimal(&block_number).unwrap() >= *tb)
+                .unwrap_or(false);
+            if interrupted.load(Ordering::SeqCst) || has_reached_terminal_block {
+                break;
+            }
 
-        let slot = StateChange {
-            block_number: block_number_u256,
-            value: latest_slot,
-        };
+            let block_number_u256: U256 = block_number.parse()?;
+            let latest_slot = source_rpc
+                .get_storage_at(contract_address.clone(), storage_slot)
+                .await?;
 
-        if storage
-            .state_changes
-            .last()
-            .map(|change| change.value != slot.value)
-            .unwrap_or(true)
-        {
-            println!(
-                "New storage slot value at block {}: {:?}",
-                slot.block_number, &slot.value
-            );
-            storage.state_changes.push(slot);
-        }
+            let slot =
+                decoder.state_change(block_number_u256, TrackedValue::parse(&latest_slot))?;
+            record_change(
+                slot,
+                &mut storage.state_changes,
+                &decoder,
+                &mut annotator,
+                (&contract_address, &Query::Slot(storage_slot)),
+                "storage slot value",
+                |change| writer.append(None, change),
+            )
+            .await?;
 
-        block_number = source_rpc.listen_for_blocks(block_listen_time).await?;
+            block_number = source_rpc.listen_for_blocks(block_listen_time).await?;
+        }
+        Ok(())
     }
+    .await;
 
-    set_filename_and_serialize(
-        path,
-        filename,
-        storage,
-        contract_address,
-        "slot",
-        storage_slot.to_string(),
-        decimal,
-    )?;
+    writer.finish(&storage)?;
+    result?;
 
     Ok(())
 }
@@ -1,18 +1,180 @@
-use ethers::types::U256;
+use crate::tracker::format::NumberFormat;
+
+use std::collections::BTreeMap;
+
+use ethers::types::{
+    I256,
+    U256,
+};
+use serde::ser::SerializeMap;
 use serde::{
     Deserialize,
+    Deserializer,
     Serialize,
+    Serializer,
 };
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct StateChange {
     pub block_number: U256,
-    pub value: String,
+    pub value: TrackedValue,
+    // The value decoded according to its type in the storage layout, if we know it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<String>,
+    // Decoded fields of a packed slot or of a call's return values, keyed by name. They're
+    // decoded from the value again when loading changes.
+    #[serde(default, skip_serializing_if = "Option::is_none", skip_deserializing)]
+    pub fields: Option<DecodedFields>,
+    // Timestamp of the block, with `--annotate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<U256>,
+    // Hashes of the txs in the block that wrote the slot, or the storage of the called
+    // contract, with `--annotate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transactions: Option<Vec<String>>,
+}
+
+// The columns of a CSV output, so every row has the same ones even when a change couldn't
+// be decoded or annotated.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CsvColumns {
+    // Names of the columns between the value and the annotations
+    pub decoded: Vec<String>,
+    // Whether rows end with the timestamp and transactions
+    pub annotated: bool,
+}
+
+impl CsvColumns {
+    // `targets` prepends the column with the name of the target, for files with several.
+    pub fn header(&self, targets: bool) -> String {
+        let mut columns = vec!["block_number", "value"];
+        if targets {
+            columns.insert(0, "target");
+        }
+        columns.extend(self.decoded.iter().map(String::as_str));
+        if self.annotated {
+            columns.extend(["timestamp", "transactions"]);
+        }
+        columns.join(",")
+    }
 }
 
 impl StateChange {
-    pub fn serialize_csv(&self) -> String {
-        format!("{},{}", self.block_number, self.value)
+    // The block number and timestamp stay decimal, like in the rest of the row. Columns
+    // the change has no value for are left empty.
+    pub fn serialize_csv(&self, format: NumberFormat, columns: &CsvColumns) -> String {
+        let mut csv = format!("{},{}", self.block_number, format.value(&self.value));
+        if let Some(decoded) = &self.decoded {
+            csv.push(',');
+            csv.push_str(&csv_escape(decoded));
+        }
+        // Fields are in the order they were declared
+        for (_, value) in self.fields.iter().flat_map(|fields| fields.0.iter()) {
+            csv.push(',');
+            csv.push_str(&csv_escape(&format.field(value)));
+        }
+        for _ in self.csv_decoded_columns()..columns.decoded.len() {
+            csv.push(',');
+        }
+        if columns.annotated {
+            let timestamp = self.timestamp.map(|timestamp| timestamp.to_string());
+            csv.push_str(&format!(",{}", timestamp.unwrap_or_default()));
+            csv.push(',');
+            csv.push_str(&self.transactions.as_deref().unwrap_or_default().join(";"));
+        }
+        csv
+    }
+
+    // Number of columns `serialize_csv` writes after the block and value for the decoded
+    // value and fields.
+    fn csv_decoded_columns(&self) -> usize {
+        self.decoded.iter().count()
+            + self
+                .fields
+                .as_ref()
+                .map(|fields| fields.0.len())
+                .unwrap_or(0)
+    }
+
+    // `target` is the name of the target the change belongs to, if the output has several.
+    pub fn formatted<'a>(
+        &'a self,
+        target: Option<&'a str>,
+        format: NumberFormat,
+    ) -> FormattedChange<'a> {
+        FormattedChange {
+            target,
+            block_number: format.integer(self.block_number),
+            value: format.value(&self.value),
+            decoded: self.decoded.as_ref(),
+            fields: self
+                .fields
+                .as_ref()
+                .map(|fields| FormattedFields { fields, format }),
+            timestamp: self.timestamp.map(|timestamp| format.integer(timestamp)),
+            transactions: self.transactions.as_ref(),
+        }
+    }
+}
+
+// A change with its numbers written in a `NumberFormat`.
+#[derive(Debug, Serialize)]
+pub struct FormattedChange<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<&'a str>,
+    block_number: String,
+    value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decoded: Option<&'a String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<FormattedFields<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transactions: Option<&'a Vec<String>>,
+}
+
+// A slot or call change list with its numbers written in a `NumberFormat`.
+#[derive(Debug, Serialize)]
+pub struct FormattedChangeList<'a> {
+    address: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    storage_slot: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    calldata: Option<&'a str>,
+    state_changes: Vec<FormattedChange<'a>>,
+}
+
+fn formatted_changes(changes: &[StateChange], format: NumberFormat) -> Vec<FormattedChange<'_>> {
+    changes
+        .iter()
+        .map(|change| change.formatted(None, format))
+        .collect()
+}
+
+// Split a CSV row written by `serialize_csv` into its columns.
+pub fn csv_split(row: &str) -> Vec<String> {
+    let mut columns = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                columns.last_mut().unwrap().push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => columns.push(String::new()),
+            (c, _) => columns.last_mut().unwrap().push(c),
+        }
+    }
+    columns
+}
+
+fn csv_escape(value: &str) -> String {
+    match value.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
     }
 }
 
@@ -20,14 +182,156 @@ impl Default for StateChange {
     fn default() -> Self {
         StateChange {
             block_number: 0.into(),
-            value: Default::default(),
+            value: TrackedValue::Raw(String::new()),
+            decoded: None,
+            fields: None,
+            timestamp: None,
+            transactions: None,
         }
     }
 }
 
+// A slot value or call result. Values of one word are kept as a number, so they can be
+// written in any `NumberFormat`, longer call results as the node returned them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackedValue {
+    Word(U256),
+    Raw(String),
+}
+
+impl TrackedValue {
+    pub fn parse(value: &str) -> Self {
+        let word = value
+            .strip_prefix("0x")
+            .filter(|digits| !digits.is_empty() && digits.len() <= 64)
+            .and_then(|digits| U256::from_str_radix(digits, 16).ok());
+        match word {
+            Some(word) => TrackedValue::Word(word),
+            None => TrackedValue::Raw(value.to_string()),
+        }
+    }
+
+    // The value as a hex string, words are padded to 32 bytes like nodes return them.
+    pub fn hex(&self) -> String {
+        match self {
+            TrackedValue::Word(word) => format!("{:#066x}", word),
+            TrackedValue::Raw(value) => value.clone(),
+        }
+    }
+}
+
+impl Serialize for TrackedValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for TrackedValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(TrackedValue::parse(&String::deserialize(deserializer)?))
+    }
+}
+
+// A decoded field. Numbers keep their type, so they can be written in any `NumberFormat`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Uint(U256),
+    Int(I256),
+    // Addresses, bools, bytes, strings, arrays and tuples
+    Other(String),
+}
+
+impl std::fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FieldValue::Uint(value) => write!(f, "{}", value),
+            FieldValue::Int(value) => write!(f, "{}", value),
+            FieldValue::Other(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// Decoded values in the order they were declared.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecodedFields(pub Vec<(String, FieldValue)>);
+
+impl DecodedFields {
+    pub fn get(&self, name: &str) -> Option<&FieldValue> {
+        self.0
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+}
+
+impl std::fmt::Display for DecodedFields {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let fields: Vec<String> = self
+            .0
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        write!(f, "{{{}}}", fields.join(", "))
+    }
+}
+
+impl Serialize for DecodedFields {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FormattedFields {
+            fields: self,
+            format: NumberFormat::Decimal,
+        }
+        .serialize(serializer)
+    }
+}
+
+// Decoded fields with their numbers written in a `NumberFormat`, serialized as a JSON object.
+#[derive(Debug)]
+pub struct FormattedFields<'a> {
+    fields: &'a DecodedFields,
+    format: NumberFormat,
+}
+
+impl Serialize for FormattedFields<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.0.len()))?;
+        for (name, value) in &self.fields.0 {
+            map.serialize_entry(name, &self.format.field(value))?;
+        }
+        map.end()
+    }
+}
+
+// How `fast_track` looks for the blocks where a slot changed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchStrategy {
+    // Query every block, or every `--query_interval` blocks
+    Scan,
+    // Query every `sample_interval` blocks, and bisect the intervals whose ends differ
+    Bisect {
+        sample_interval: u64,
+        // Use `trace_filter` to find changes in intervals whose ends are equal
+        confirm_with_traces: bool,
+    },
+}
+
 pub trait SerializeStorage {
-    fn serialize_json(&self) -> Result<String, serde_json::Error>;
-    fn serialize_csv(&self) -> String;
+    // Whether changes belong to one of several named targets
+    const TARGETS: bool = false;
+
+    fn serialize_json(&self, format: NumberFormat) -> Result<String, serde_json::Error>;
+    // Every change, with the name of its target if there are several
+    fn changes(&self) -> Vec<(Option<&str>, &StateChange)>;
+    fn push(&mut self, target: Option<&str>, change: StateChange) -> Result<(), String>;
+    // Why `other` doesn't track the same thing, if it doesn't
+    fn mismatch(&self, other: &Self) -> Option<String>;
+}
+
+fn address_mismatch(address: &str, other: &str) -> Option<String> {
+    match address.eq_ignore_ascii_case(other) {
+        true => None,
+        false => Some(format!("address is {}, not {}", other, address)),
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -37,18 +341,42 @@ pub struct StateChangeList {
     pub state_changes: Vec<StateChange>,
 }
 
+impl StateChangeList {
+    pub fn formatted(&self, format: NumberFormat) -> FormattedChangeList<'_> {
+        FormattedChangeList {
+            address: &self.address,
+            storage_slot: Some(format.integer(self.storage_slot)),
+            calldata: None,
+            state_changes: formatted_changes(&self.state_changes, format),
+        }
+    }
+}
+
 impl SerializeStorage for StateChangeList {
-    fn serialize_json(&self) -> Result<String, serde_json::Error> {
-        serde_json::to_string(&self)
+    fn serialize_json(&self, format: NumberFormat) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&self.formatted(format))
+    }
+
+    fn changes(&self) -> Vec<(Option<&str>, &StateChange)> {
+        self.state_changes
+            .iter()
+            .map(|change| (None, change))
+            .collect()
+    }
+
+    fn push(&mut self, _target: Option<&str>, change: StateChange) -> Result<(), String> {
+        self.state_changes.push(change);
+        Ok(())
     }
 
-    fn serialize_csv(&self) -> String {
-        let mut csv = String::new();
-        for change in &self.state_changes {
-            csv.push_str(&change.serialize_csv());
-            csv.push('\n');
+    fn mismatch(&self, other: &Self) -> Option<String> {
+        if self.storage_slot != other.storage_slot {
+            return Some(format!(
+                "storage slot is {}, not {}",
+                other.storage_slot, self.storage_slot
+            ));
         }
-        csv
+        address_mismatch(&self.address, &other.address)
     }
 }
 
@@ -59,17 +387,329 @@ pub struct CallChangeList {
     pub state_changes: Vec<StateChange>,
 }
 
+impl CallChangeList {
+    pub fn formatted(&self, format: NumberFormat) -> FormattedChangeList<'_> {
+        FormattedChangeList {
+            address: &self.address,
+            storage_slot: None,
+            calldata: Some(&self.calldata),
+            state_changes: formatted_changes(&self.state_changes, format),
+        }
+    }
+}
+
 impl SerializeStorage for CallChangeList {
-    fn serialize_json(&self) -> Result<String, serde_json::Error> {
-        serde_json::to_string(&self)
+    fn serialize_json(&self, format: NumberFormat) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&self.formatted(format))
+    }
+
+    fn changes(&self) -> Vec<(Option<&str>, &StateChange)> {
+        self.state_changes
+            .iter()
+            .map(|change| (None, change))
+            .collect()
+    }
+
+    fn push(&mut self, _target: Option<&str>, change: StateChange) -> Result<(), String> {
+        self.state_changes.push(change);
+        Ok(())
     }
 
-    fn serialize_csv(&self) -> String {
-        let mut csv = String::new();
-        for change in &self.state_changes {
-            csv.push_str(&change.serialize_csv());
-            csv.push('\n');
+    fn mismatch(&self, other: &Self) -> Option<String> {
+        if !self.calldata.eq_ignore_ascii_case(&other.calldata) {
+            return Some(format!(
+                "calldata is {}, not {}",
+                other.calldata, self.calldata
+            ));
         }
-        csv
+        address_mismatch(&self.address, &other.address)
+    }
+}
+
+// Targets tracked together by `multi_track`, loaded from `--spec_file`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrackSpec {
+    pub targets: Vec<TrackTarget>,
+    // solc/forge storage layout used to resolve the `variable` of targets
+    pub storage_layout: Option<String>,
+    // Write all targets to one file keyed by name, instead of a file per target
+    #[serde(default)]
+    pub combined: bool,
+    // `json`, `ndjson` or `csv`
+    #[serde(default = "default_format")]
+    pub format: String,
+}
+
+fn default_format() -> String {
+    "json".to_string()
+}
+
+// A storage slot or call to track. Exactly one of `slot`, `variable`, `calldata` and `signature`
+// has to be set.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrackTarget {
+    pub name: String,
+    pub address: String,
+    // Decimal, or hex if prefixed with 0x
+    pub slot: Option<String>,
+    // Variable path resolved with the `storage_layout` of the spec, e.g. `balances[0xabc...]`
+    pub variable: Option<String>,
+    pub calldata: Option<String>,
+    // Function signature with return types to encode the call with, instead of `calldata`
+    pub signature: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // Fields packed in the slot to decode
+    #[serde(default)]
+    pub fields: Vec<SlotField>,
+    // Only count changes of these fields, all of them if empty
+    #[serde(default)]
+    pub watch: Vec<String>,
+}
+
+// The changes of a single target of `multi_track`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum TargetChangeList {
+    Slot(StateChangeList),
+    Call(CallChangeList),
+}
+
+impl TargetChangeList {
+    pub fn state_changes(&self) -> &Vec<StateChange> {
+        match self {
+            TargetChangeList::Slot(list) => &list.state_changes,
+            TargetChangeList::Call(list) => &list.state_changes,
+        }
+    }
+
+    pub fn state_changes_mut(&mut self) -> &mut Vec<StateChange> {
+        match self {
+            TargetChangeList::Slot(list) => &mut list.state_changes,
+            TargetChangeList::Call(list) => &mut list.state_changes,
+        }
+    }
+
+    pub fn formatted(&self, format: NumberFormat) -> FormattedChangeList<'_> {
+        match self {
+            TargetChangeList::Slot(list) => list.formatted(format),
+            TargetChangeList::Call(list) => list.formatted(format),
+        }
+    }
+}
+
+impl SerializeStorage for TargetChangeList {
+    fn serialize_json(&self, format: NumberFormat) -> Result<String, serde_json::Error> {
+        serde_json::to_string(&self.formatted(format))
+    }
+
+    fn changes(&self) -> Vec<(Option<&str>, &StateChange)> {
+        self.state_changes()
+            .iter()
+            .map(|change| (None, change))
+            .collect()
+    }
+
+    fn push(&mut self, _target: Option<&str>, change: StateChange) -> Result<(), String> {
+        self.state_changes_mut().push(change);
+        Ok(())
+    }
+
+    fn mismatch(&self, other: &Self) -> Option<String> {
+        match (self, other) {
+            (TargetChangeList::Slot(list), TargetChangeList::Slot(other)) => list.mismatch(other),
+            (TargetChangeList::Call(list), TargetChangeList::Call(other)) => list.mismatch(other),
+            (TargetChangeList::Slot(_), TargetChangeList::Call(_)) => {
+                Some("it tracks a call, not a storage slot".to_string())
+            }
+            (TargetChangeList::Call(_), TargetChangeList::Slot(_)) => {
+                Some("it tracks a storage slot, not a call".to_string())
+            }
+        }
+    }
+}
+
+// The changes of every target of `multi_track` in one dataset, keyed by target name.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CombinedChangeList {
+    pub targets: BTreeMap<String, TargetChangeList>,
+}
+
+impl SerializeStorage for CombinedChangeList {
+    const TARGETS: bool = true;
+
+    fn serialize_json(&self, format: NumberFormat) -> Result<String, serde_json::Error> {
+        #[derive(Serialize)]
+        struct FormattedTargets<'a> {
+            targets: BTreeMap<&'a str, FormattedChangeList<'a>>,
+        }
+
+        serde_json::to_string(&FormattedTargets {
+            targets: self
+                .targets
+                .iter()
+                .map(|(name, list)| (name.as_str(), list.formatted(format)))
+                .collect(),
+        })
+    }
+
+    fn changes(&self) -> Vec<(Option<&str>, &StateChange)> {
+        let mut changes: Vec<(Option<&str>, &StateChange)> = self
+            .targets
+            .iter()
+            .flat_map(|(name, list)| {
+                list.state_changes()
+                    .iter()
+                    .map(move |change| (Some(name.as_str()), change))
+            })
+            .collect();
+        // The same order they were found in
+        changes.sort_by_key(|(_, change)| change.block_number);
+        changes
+    }
+
+    fn push(&mut self, target: Option<&str>, change: StateChange) -> Result<(), String> {
+        let target = target.ok_or("Change has no target")?;
+        self.targets
+            .get_mut(target)
+            .ok_or(format!("Unknown target {}", target))?
+            .state_changes_mut()
+            .push(change);
+        Ok(())
+    }
+
+    fn mismatch(&self, other: &Self) -> Option<String> {
+        let names: Vec<&String> = self.targets.keys().collect();
+        let other_names: Vec<&String> = other.targets.keys().collect();
+        if names != other_names {
+            return Some(format!("targets are {:?}, not {:?}", other_names, names));
+        }
+        self.targets.iter().find_map(|(name, list)| {
+            list.mismatch(&other.targets[name])
+                .map(|mismatch| format!("target {}: {}", name, mismatch))
+        })
+    }
+}
+
+// The `storageLayout` output of solc and forge.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageLayout {
+    pub storage: Vec<StorageEntry>,
+    // solc outputs `null` if the contract has no storage variables
+    #[serde(default)]
+    pub types: Option<BTreeMap<String, StorageType>>,
+}
+
+// A state variable, or a member of a struct.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageEntry {
+    pub label: String,
+    pub offset: usize,
+    pub slot: String,
+    #[serde(rename = "type")]
+    pub type_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageType {
+    // `inplace`, `mapping`, `dynamic_array` or `bytes`
+    pub encoding: String,
+    pub label: String,
+    #[serde(rename = "numberOfBytes")]
+    pub number_of_bytes: String,
+    // Key and value types of mappings
+    pub key: Option<String>,
+    pub value: Option<String>,
+    // Element type of arrays
+    pub base: Option<String>,
+    // Members of structs
+    pub members: Option<Vec<StorageEntry>>,
+}
+
+// A field packed in a storage slot, e.g. `tick` in the `slot0` of Uniswap V3 pools.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SlotField {
+    pub name: String,
+    // Byte offset of the field in the slot, counted from the right like in storage layouts
+    pub offset: usize,
+    pub size: usize,
+    // `uint`, `int`, `bool`, `address` or `bytesN`, sizes like `uint160` are allowed
+    #[serde(rename = "type")]
+    pub field_type: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_split_back_into_their_columns() {
+        let values = [
+            "plain",
+            "with,comma",
+            "with \"quotes\"",
+            "\"quoted, with comma\"",
+            "",
+            "[1,2,3]",
+        ];
+
+        let row: Vec<String> = values.iter().map(|value| csv_escape(value)).collect();
+        assert_eq!(csv_split(&row.join(",")), values);
+    }
+
+    #[test]
+    fn changes_serialize_to_splittable_rows() {
+        let change = StateChange {
+            block_number: 17799350.into(),
+            value: TrackedValue::parse("0x01"),
+            decoded: Some("a, \"b\"".to_string()),
+            fields: Some(DecodedFields(vec![
+                (
+                    "amounts".to_string(),
+                    FieldValue::Other("[1,2]".to_string()),
+                ),
+                ("ok".to_string(), FieldValue::Other("true".to_string())),
+            ])),
+            timestamp: Some(1700000000.into()),
+            transactions: Some(vec!["0xaa".to_string(), "0xbb".to_string()]),
+        };
+
+        let columns = CsvColumns {
+            decoded: vec![
+                "decoded".to_string(),
+                "amounts".to_string(),
+                "ok".to_string(),
+            ],
+            annotated: true,
+        };
+        let row = change.serialize_csv(NumberFormat::Hex, &columns);
+        assert_eq!(
+            csv_split(&row),
+            [
+                "17799350",
+                "0x0000000000000000000000000000000000000000000000000000000000000001",
+                "a, \"b\"",
+                "[1,2]",
+                "true",
+                "1700000000",
+                "0xaa;0xbb"
+            ]
+        );
+        assert_eq!(
+            columns.header(false),
+            "block_number,value,decoded,amounts,ok,timestamp,transactions"
+        );
+
+        // A result that couldn't be decoded keeps the annotations in their columns
+        let undecoded = StateChange {
+            decoded: None,
+            fields: None,
+            transactions: Some(Vec::new()),
+            ..change
+        };
+        assert_eq!(
+            csv_split(&undecoded.serialize_csv(NumberFormat::Hex, &columns))[5..],
+            ["1700000000", ""]
+        );
     }
 }